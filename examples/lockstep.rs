@@ -0,0 +1,67 @@
+// Plays a networked versus game between two threads over localhost, with random actions on both
+// sides, and checks that the checksums the two sides exchange along the way always agree.
+extern crate gliumtetris;
+extern crate rand;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use gliumtetris::controls::Action;
+use gliumtetris::netcode::{pose_checksum, Lockstep};
+
+const ADDRESS: &'static str = "127.0.0.1:7778";
+const TICKS: u64 = 3000;
+
+const ACTIONS: [Action; 7] = [
+    Action::RotateCW, Action::RotateCCW, Action::RotateStop,
+    Action::MoveLeft, Action::MoveRight, Action::MoveStop,
+    Action::TrySpawn,
+];
+
+// Play for a number of ticks. Returns the tick reached, and the checksum of the games at that point.
+fn play(mut lockstep: Lockstep, seed: u32) -> Result<(u64, u64), String> {
+    let mut rng = XorShiftRng::from_seed([seed + 1, 2, 3, 4]);
+    while lockstep.tick() < TICKS {
+        if rng.gen_weighted_bool(10) {
+            lockstep.queue(*rng.choose(&ACTIONS).unwrap());
+        }
+        let before = lockstep.tick();
+        lockstep.update();
+        if let Some(desync) = lockstep.desync() {
+            return Err(format!("out of sync since tick {}", desync.tick));
+        }
+        // The other side may finish first; what it sent before disconnecting is still played
+        if lockstep.error().is_some() && lockstep.tick() == before {
+            return Err(format!("{} at tick {}", lockstep.error().unwrap(), before));
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    Ok((lockstep.tick(), pose_checksum(&[lockstep.versus().game(0), lockstep.versus().game(1)])))
+}
+
+fn main() {
+    let (sender, results) = mpsc::channel();
+    let host_sender = sender.clone();
+    let host = thread::spawn(move || {
+        let result = Lockstep::host(ADDRESS).and_then(|lockstep| play(lockstep, 0));
+        host_sender.send(("host", result)).unwrap();
+    });
+    // Give the host a moment to start listening
+    thread::sleep(Duration::from_millis(200));
+    let client = thread::spawn(move || {
+        let result = Lockstep::join(ADDRESS).and_then(|lockstep| play(lockstep, 1));
+        sender.send(("client", result)).unwrap();
+    });
+    host.join().unwrap();
+    client.join().unwrap();
+
+    for (side, result) in results.iter().take(2) {
+        match result {
+            Ok((tick, checksum)) => println!("{}: in sync up to tick {}, checksum {:016x}", side, tick, checksum),
+            Err(error) => panic!("{}: {}", side, error),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+// Plays a few episodes with random actions in the headless environment, and checks that replaying
+// an episode with the same seed and actions gives the same result.
+extern crate gliumtetris;
+extern crate rand;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use gliumtetris::env::{Env, EnvAction, EnvConfig, Observation, ACTION_COUNT};
+
+const EPISODES: u32 = 3;
+const MAX_STEPS: usize = 2000;
+
+fn run_episode(env: &mut Env, seed: u32) -> (f32, usize, Observation) {
+    let mut rng = XorShiftRng::from_seed([seed + 1, 2, 3, 4]);
+    let mut observation = env.reset(seed);
+    let mut total_reward = 0.0;
+    loop {
+        let (next, reward, done) = env.step(EnvAction::from_index(rng.gen_range(0, ACTION_COUNT)));
+        observation = next;
+        total_reward += reward;
+        if done { break; }
+    }
+    (total_reward, env.steps(), observation)
+}
+
+fn main() {
+    let mut config = EnvConfig::new();
+    config.max_steps = Some(MAX_STEPS);
+    let mut env = Env::new(config);
+
+    for seed in 0..EPISODES {
+        let (reward, steps, observation) = run_episode(&mut env, seed);
+        let (replay_reward, replay_steps, replay_observation) = run_episode(&mut env, seed);
+        let bodies = observation.bodies.unwrap();
+        let replay_bodies = replay_observation.bodies.unwrap();
+        let identical = reward == replay_reward && steps == replay_steps &&
+            observation.grid == replay_observation.grid &&
+            bodies.len() == replay_bodies.len() &&
+            bodies.iter().zip(replay_bodies.iter()).all(|(a, b)| a.position == b.position && a.angle == b.angle);
+        println!("seed {}: reward {} after {} steps, {} objects, replay {}",
+                 seed, reward, steps, bodies.len(), if identical { "identical" } else { "DIFFERENT" });
+        assert!(identical, "replaying seed {} gave a different result", seed);
+    }
+}
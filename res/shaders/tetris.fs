@@ -1,5 +1,5 @@
 #version 140
-in vec3 f_color;
+in vec4 f_color;
 void main() {
-    gl_FragColor = vec4(f_color, 1.0);
+    gl_FragColor = f_color;
 }
@@ -1,8 +1,8 @@
 #version 140
 
 in vec2 position;
-in vec3 color;
-out vec3 f_color;
+in vec4 color;
+out vec4 f_color;
 
 uniform mat4 proj;
 
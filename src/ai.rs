@@ -0,0 +1,351 @@
+use std::cmp;
+use std::f32::consts::PI;
+
+use na::{Norm, Point2, Rotate, Rotation, Rotation2, Transform, Vector1, Vector2};
+use nphysics2d::object::RigidBody;
+
+use consts::*;
+use controls::Action;
+use game::*;
+
+// Rotations of the polyomino grid, relative to the well
+const ANGLES: [f32; 4] = [0.0, PI / 2.0, PI, 3.0 * PI / 2.0];
+// Physics steps simulated per frame by the search, so that it doesn't stall the game
+const TICKS_PER_FRAME: usize = 240;
+const MIN_SIMULATION_TICKS: usize = 30;
+const MAX_SIMULATION_TICKS: usize = 300;
+const SETTLED_SPEED: f32 = 0.1;
+// Desired (angular) velocity per unit of distance to the target
+const ANGLE_GAIN: f32 = 4.0;
+const POSITION_GAIN: f32 = 3.0;
+const ANGLE_TOLERANCE: f32 = 0.1;
+const POSITION_TOLERANCE: f32 = 0.1;
+// Let go of a piece that doesn't reach its target within this many ticks
+const PATIENCE_TICKS: u64 = 600;
+// Weights of the board evaluation
+const HEIGHT_WEIGHT: f32 = 0.5;
+const HOLE_WEIGHT: f32 = 3.0;
+const BUMPINESS_WEIGHT: f32 = 0.3;
+const LINE_WEIGHT: f32 = 4.0;
+const CLEAR_WEIGHT: f32 = 10.0;
+const TOP_OUT_PENALTY: f32 = 100.0;
+
+// Where the bot wants the center of mass of a piece to end up, and at which rotation
+#[derive(Copy, Clone, Debug)]
+pub struct Target {
+    pub x: f32,
+    pub angle: f32,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Role {
+    // The bot plays instead of the player
+    Player,
+    // The bot plays an attract-mode demo, until a key is pressed
+    Demo,
+}
+
+impl Role {
+    pub fn describe(&self) -> &'static str {
+        match *self {
+            Role::Player => "Bot playing (F11 to take over)",
+            Role::Demo => "Demo - press any key to play",
+        }
+    }
+}
+
+// The candidates for the current piece, searched a few physics steps at a time
+struct Search {
+    // A copy of the game as it was when the search started
+    base: Game,
+    candidates: Vec<Target>,
+    next: usize,
+    // The candidate being simulated, which can take several frames
+    current: Option<Simulation>,
+    best: Option<(f32, Target)>,
+}
+
+// Steering the piece to a target in a copy of the game
+struct Simulation {
+    target: Target,
+    game: Game,
+    piece: usize,
+    tick: usize,
+    finished: bool,
+}
+
+pub struct Bot {
+    role: Role,
+    // The number of the piece being steered, and the tick at which the bot got it
+    piece: Option<(usize, u64)>,
+    search: Option<Search>,
+    target: Option<Target>,
+}
+
+impl Bot {
+    pub fn new(role: Role) -> Self {
+        Bot {
+            role: role,
+            piece: None,
+            search: None,
+            target: None,
+        }
+    }
+
+    pub fn role(&self) -> Role { self.role }
+
+    // The best target found so far for the current piece
+    pub fn target(&self) -> Option<Target> { self.target }
+
+    // Decide what to do this frame. Returns the actions to execute on the game.
+    pub fn update(&mut self, game: &Game) -> Vec<Action> {
+        let mut actions = vec![];
+        if !game.accepting_input() {
+            return actions;
+        }
+        if game.control_object().is_none() {
+            self.piece = None;
+            actions.push(Action::TrySpawn);
+            return actions;
+        }
+
+        // Start searching as soon as there's a new piece
+        let piece = game.pieces_spawned();
+        if self.piece.map(|(piece, _)| piece) != Some(piece) {
+            self.piece = Some((piece, game.ticks()));
+            let candidates = candidates(game.control_object().unwrap());
+            // Previews leave the game alone, which a replay of it relies on. The simulations are
+            // never rewound, so they don't need snapshots.
+            let mut base = game.preview();
+            base.set_rewind_history(0);
+            self.search = Some(Search { base: base, candidates: candidates, next: 0, current: None, best: None });
+            self.target = None;
+        }
+        let searched = match self.search {
+            Some(ref mut search) => {
+                let mut budget = TICKS_PER_FRAME;
+                while budget > 0 {
+                    if search.current.is_none() {
+                        if search.next == search.candidates.len() { break; }
+                        search.current = Some(Simulation::new(&search.base, search.candidates[search.next]));
+                        search.next += 1;
+                    }
+                    let finished = {
+                        let simulation = search.current.as_mut().unwrap();
+                        budget -= simulation.run(budget);
+                        simulation.finished
+                    };
+                    if finished {
+                        let simulation = search.current.take().unwrap();
+                        let score = simulation.score(&search.base);
+                        if search.best.map_or(true, |(best, _)| score > best) {
+                            search.best = Some((score, simulation.target));
+                        }
+                    }
+                }
+                // Head for the best candidate so far
+                self.target = search.best.map(|(_, target)| target);
+                search.next == search.candidates.len() && search.current.is_none()
+            },
+            None => false,
+        };
+        if searched {
+            self.search = None;
+        }
+
+        let target = match self.target {
+            Some(target) => target,
+            None => return actions,
+        };
+        let obj = game.control_object().unwrap();
+        let rb = obj.rbh.borrow();
+        actions.extend(steering_actions(game, &rb, target));
+
+        // Let go of the piece by spawning the next one, once it's in place (or taking too long)
+        let since = self.piece.map_or(0, |(_, since)| since);
+        let in_place = (rb.center_of_mass().x - target.x).abs() < POSITION_TOLERANCE &&
+            angle_difference(target.angle, angle(&rb)).abs() < ANGLE_TOLERANCE &&
+            settled(&rb);
+        if self.search.is_none() && (in_place || game.ticks().saturating_sub(since) > PATIENCE_TICKS) {
+            actions.push(Action::TrySpawn);
+        }
+        actions
+    }
+}
+
+// Every rotation and column the piece could be dropped at, with its blocks aligned to the columns
+fn candidates(obj: &Tetromino) -> Vec<Target> {
+    let rb = obj.rbh.borrow();
+    let local_com = rb.position().inverse_transform(rb.center_of_mass());
+    // Block centers relative to the center of mass, in the frame of the piece
+    let offsets: Vec<Vector2<f32>> = obj.blocks().iter()
+        .map(|&(inner_iso, _)| inner_iso.translation - local_com.to_vector())
+        .collect();
+
+    let mut candidates = vec![];
+    for &angle in ANGLES.iter() {
+        let rotation = Rotation2::new(Vector1::new(angle));
+        let xs: Vec<f32> = offsets.iter().map(|offset| rotation.rotate(offset).x).collect();
+        let min_x = xs.iter().cloned().fold(::std::f32::INFINITY, f32::min);
+        let max_x = xs.iter().cloned().fold(::std::f32::NEG_INFINITY, f32::max);
+        for column in 0..BLOCKS_PER_LINE {
+            // Put the first block in this column
+            let x = LEFT + BLOCK_DIST * (column as f32 + 0.5) - xs[0];
+            if x + min_x < LEFT || x + max_x > RIGHT { continue; }
+            candidates.push(Target { x: x, angle: angle });
+        }
+    }
+    candidates
+}
+
+impl Simulation {
+    fn new(base: &Game, target: Target) -> Self {
+        Simulation {
+            target: target,
+            // The base was never stepped, so every preview of it plays out alike
+            game: base.preview(),
+            piece: base.pieces_spawned(),
+            tick: 0,
+            finished: false,
+        }
+    }
+
+    // Simulate at most `budget` physics steps, until the piece settles. Returns the number of steps.
+    fn run(&mut self, budget: usize) -> usize {
+        let mut ticks = 0;
+        while ticks < budget && !self.finished {
+            // Stop once the piece is no longer controlled, e.g. because it completed a line
+            if self.tick >= MAX_SIMULATION_TICKS || self.game.pieces_spawned() != self.piece {
+                self.finished = true;
+                break;
+            }
+            let actions = match self.game.control_object() {
+                Some(obj) => {
+                    let rb = obj.rbh.borrow();
+                    if self.tick >= MIN_SIMULATION_TICKS && settled(&rb) { None }
+                    else { Some(steering_actions(&self.game, &rb, self.target)) }
+                },
+                None => None,
+            };
+            match actions {
+                Some(actions) => for action in actions {
+                    self.game.execute_action(action);
+                },
+                None => {
+                    self.finished = true;
+                    break;
+                },
+            }
+            self.game.update();
+            self.tick += 1;
+            ticks += 1;
+        }
+        ticks
+    }
+
+    // Score the board the simulation ended with
+    fn score(&self, base: &Game) -> f32 {
+        let centers: Vec<Point2<f32>> = self.game.tetrominos()
+            .flat_map(|tetr| {
+                let iso = *tetr.rbh.borrow().position();
+                tetr.blocks().into_iter().map(move |(inner_iso, _)| iso * inner_iso.translation.to_point())
+            }).collect();
+        let lines = self.game.lines() - base.lines();
+        CLEAR_WEIGHT * lines as f32 + evaluate_board(&centers)
+    }
+}
+
+// Score a board by its block centers: low, without holes, flat, and with nearly complete lines is good
+fn evaluate_board(centers: &[Point2<f32>]) -> f32 {
+    let rows = ((TOP - BOTTOM) / BLOCK_DIST).ceil() as usize;
+    let mut grid = vec![[false; BLOCKS_PER_LINE]; rows];
+    let mut topped_out = false;
+    for center in centers {
+        let column = ((center.x - LEFT) / BLOCK_DIST).floor().max(0.0) as usize;
+        let row = ((center.y - BOTTOM) / BLOCK_DIST).floor().max(0.0) as usize;
+        if row < rows {
+            grid[row][cmp::min(column, BLOCKS_PER_LINE - 1)] = true;
+        }
+        topped_out |= center.y > TOP_OUT_HEIGHT;
+    }
+
+    let heights: Vec<usize> = (0..BLOCKS_PER_LINE)
+        .map(|column| (0..rows).rev().find(|&row| grid[row][column]).map_or(0, |row| row + 1))
+        .collect();
+    let holes = (0..BLOCKS_PER_LINE)
+        .map(|column| (0..heights[column]).filter(|&row| !grid[row][column]).count())
+        .sum::<usize>();
+    let bumpiness = heights.windows(2)
+        .map(|pair| (pair[0] as i32 - pair[1] as i32).abs())
+        .sum::<i32>();
+    // Fuller lines count quadratically
+    let lines = grid.iter()
+        .map(|row| {
+            let fill = row.iter().filter(|&&occupied| occupied).count() as f32 / BLOCKS_PER_LINE as f32;
+            fill * fill
+        }).sum::<f32>();
+
+    let mut score = LINE_WEIGHT * lines
+        - HEIGHT_WEIGHT * heights.iter().sum::<usize>() as f32
+        - HOLE_WEIGHT * holes as f32
+        - BUMPINESS_WEIGHT * bumpiness as f32;
+    if topped_out {
+        score -= TOP_OUT_PENALTY;
+    }
+    score
+}
+
+// The actions that change the controls of the game to the ones that steer the piece towards the target
+fn steering_actions(game: &Game, rb: &RigidBody<f32>, target: Target) -> Vec<Action> {
+    let mut actions = vec![];
+    let (rotate, mov) = steer(rb, target);
+    let (current_rotate, current_mov) = game.controls();
+    if rotate != current_rotate {
+        actions.push(match rotate {
+            Some(RotateMove::Clockwise) => Action::RotateCW,
+            Some(RotateMove::Counterclockwise) => Action::RotateCCW,
+            None => Action::RotateStop,
+        });
+    }
+    if mov != current_mov {
+        actions.push(match mov {
+            Some(Move::Left) => Action::MoveLeft,
+            Some(Move::Right) => Action::MoveRight,
+            None => Action::MoveStop,
+        });
+    }
+    actions
+}
+
+// The controls that move a piece towards the target. Clockwise rotation increases the angle.
+fn steer(rb: &RigidBody<f32>, target: Target) -> (Option<RotateMove>, Option<Move>) {
+    let desired_ang_vel = ANGLE_GAIN * angle_difference(target.angle, angle(rb));
+    let rotate = match desired_ang_vel - rb.ang_vel().x {
+        error if error > ANGLE_TOLERANCE => Some(RotateMove::Clockwise),
+        error if error < -ANGLE_TOLERANCE => Some(RotateMove::Counterclockwise),
+        _ => None,
+    };
+    let desired_lin_vel = POSITION_GAIN * (target.x - rb.center_of_mass().x);
+    let mov = match desired_lin_vel - rb.lin_vel().x {
+        error if error > POSITION_TOLERANCE => Some(Move::Right),
+        error if error < -POSITION_TOLERANCE => Some(Move::Left),
+        _ => None,
+    };
+    (rotate, mov)
+}
+
+fn angle(rb: &RigidBody<f32>) -> f32 {
+    rb.position().rotation().x
+}
+
+// The difference between two angles, between -π and π
+fn angle_difference(a: f32, b: f32) -> f32 {
+    let difference = (a - b) % (2.0 * PI);
+    if difference > PI { difference - 2.0 * PI }
+    else if difference < -PI { difference + 2.0 * PI }
+    else { difference }
+}
+
+fn settled(rb: &RigidBody<f32>) -> bool {
+    rb.lin_vel().norm() < SETTLED_SPEED && rb.ang_vel().x.abs() < SETTLED_SPEED
+}
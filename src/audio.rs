@@ -0,0 +1,293 @@
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+use rand::{self, Rng, XorShiftRng};
+
+#[cfg(feature="audio")]
+use rodio;
+
+use consts::*;
+use events::GameEvent;
+
+const SAMPLE_RATE: u32 = 44100;
+const SPAWN_FREQ: f32 = 520.0;
+const CLEAR_FREQ: f32 = 440.0;
+// Every step of a combo raises the pitch of the line clear sound by this many semitones
+const COMBO_SEMITONES: f32 = 2.0;
+// Impacts with this estimated impulse (or more) are played at full volume
+const LOUD_ESTIMATED_IMPULSE: f32 = 4.0;
+const MUSIC_VOLUME: f32 = 0.25;
+const MUSIC_BASE_TEMPO: f32 = 100.0;
+const MUSIC_TEMPO_PER_LEVEL: f32 = 8.0;
+// Semitones above A3 of the root of each bar
+const MUSIC_PROGRESSION: [f32; 4] = [0.0, -4.0, 3.0, -2.0];
+
+// Where the synthesized sounds go
+pub enum Output {
+    // Sounds are never synthesized or played. Used for headless runs, or when there's no audio device.
+    Null,
+    #[cfg(feature="audio")]
+    Device(rodio::Endpoint),
+}
+
+impl Output {
+    // The default audio device, if there is one
+    #[cfg(feature="audio")]
+    pub fn device() -> Output {
+        match rodio::get_default_endpoint() {
+            Some(endpoint) => Output::Device(endpoint),
+            None => Output::Null,
+        }
+    }
+
+    #[cfg(not(feature="audio"))]
+    pub fn device() -> Output {
+        Output::Null
+    }
+
+    fn is_null(&self) -> bool {
+        match *self {
+            Output::Null => true,
+            #[cfg(feature="audio")]
+            Output::Device(_) => false,
+        }
+    }
+
+    fn play(&self, samples: Vec<f32>) {
+        match *self {
+            Output::Null => {},
+            #[cfg(feature="audio")]
+            Output::Device(ref endpoint) => {
+                rodio::play_raw(endpoint, rodio::buffer::SamplesBuffer::new(1, SAMPLE_RATE, samples));
+            },
+        }
+    }
+}
+
+// Sound effects and music, synthesized on the fly from game events.
+pub struct Audio {
+    output: Output,
+    volume: f32,
+    muted: bool,
+    level: usize,
+    // The moment the next bar of music should start, if the music is playing
+    next_bar: Option<Instant>,
+    bar: usize,
+    rng: XorShiftRng,
+}
+
+impl Audio {
+    pub fn new(output: Output) -> Self {
+        Audio {
+            output: output,
+            volume: DEFAULT_VOLUME,
+            muted: false,
+            level: 1,
+            next_bar: Some(Instant::now()),
+            bar: 0,
+            rng: rand::thread_rng().gen(),
+        }
+    }
+
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+    }
+
+    pub fn change_volume(&mut self, delta: f32) {
+        self.volume = (self.volume + delta).max(0.0).min(1.0);
+    }
+
+    // Restart the music for a new game
+    pub fn reset(&mut self) {
+        self.level = 1;
+        self.bar = 0;
+        self.next_bar = Some(Instant::now());
+    }
+
+    pub fn handle_event(&mut self, event: &GameEvent) {
+        match *event {
+            GameEvent::LevelChanged { level } => self.level = level,
+            GameEvent::GameOver { .. } => self.next_bar = None,
+            _ => {},
+        }
+        if !self.audible() { return; }
+
+        let samples = match *event {
+            GameEvent::Spawned { .. } => {
+                synth(0.09, |t, p| square(SPAWN_FREQ * (1.0 + 2.0 * p) * t) * 0.3 * (1.0 - p))
+            },
+            GameEvent::Impact { estimated_impulse, .. } => {
+                let loudness = (estimated_impulse / LOUD_ESTIMATED_IMPULSE).min(1.0);
+                let rng = &mut self.rng;
+                synth(0.12, |t, p| {
+                    let thump = sine(90.0 * (1.0 - 0.5 * p) * t);
+                    let noise = rng.gen_range(-1.0, 1.0);
+                    loudness * (0.7 * thump + 0.3 * noise) * (1.0 - p) * (1.0 - p)
+                })
+            },
+            // The clear sound is an arpeggio with one note per line, pitched up by the combo
+            GameEvent::ScoreChanged { ref event, .. } => {
+                let root = CLEAR_FREQ * semitones(COMBO_SEMITONES * event.combo as f32);
+                let notes: Vec<f32> = [0.0, 4.0, 7.0, 12.0].iter().cycle()
+                    .take(event.lines + 1)
+                    .map(|&interval| root * semitones(interval))
+                    .collect();
+                arpeggio(&notes, 0.07, 0.35)
+            },
+            GameEvent::LevelChanged { .. } => {
+                arpeggio(&[CLEAR_FREQ, CLEAR_FREQ * semitones(7.0), CLEAR_FREQ * 2.0], 0.1, 0.3)
+            },
+            // A quick downward sweep
+            GameEvent::Rewound { .. } => {
+                synth(0.25, |t, p| sine(SPAWN_FREQ * 2.0 * (1.0 - 0.6 * p) * t) * 0.3 * (1.0 - p))
+            },
+            GameEvent::GameOver { .. } => {
+                let notes: Vec<f32> = [12.0, 7.0, 3.0, 0.0].iter().map(|&interval| 220.0 * semitones(interval)).collect();
+                arpeggio(&notes, 0.22, 0.35)
+            },
+            _ => return,
+        };
+        self.play(samples);
+    }
+
+    // Queue the next bar of music when it's due. The tempo goes up with the level.
+    pub fn update(&mut self) {
+        let start = match self.next_bar {
+            Some(start) if Instant::now() >= start => start,
+            _ => return,
+        };
+        let tempo = MUSIC_BASE_TEMPO + MUSIC_TEMPO_PER_LEVEL * (self.level - 1) as f32;
+        let beat = 60.0 / tempo;
+        let root = 220.0 * semitones(MUSIC_PROGRESSION[self.bar % MUSIC_PROGRESSION.len()]);
+        self.bar += 1;
+
+        // A bass note on every beat, with a minor arpeggio on top in eighth notes
+        if self.audible() {
+            let pattern = [0.0, 3.0, 7.0, 12.0, 15.0, 12.0, 7.0, 3.0];
+            let samples = synth(4.0 * beat, |t, p| {
+                let position = p * 4.0;
+                let in_beat = position.fract();
+                let eighth = (position * 2.0) as usize;
+                let in_eighth = (position * 2.0).fract();
+                let bass = triangle(root / 2.0 * t) * (1.0 - in_beat).powi(2);
+                let lead = square(root * 2.0 * semitones(pattern[eighth % pattern.len()]) * t) * (1.0 - in_eighth);
+                MUSIC_VOLUME * (0.6 * bass + 0.15 * lead)
+            });
+            self.play(samples);
+        }
+
+        // Don't try to catch up with bars that were missed, e.g. while the window was being moved
+        let bar_duration = Duration::from_millis((4000.0 * beat) as u64);
+        self.next_bar = Some(::std::cmp::max(start + bar_duration, Instant::now()));
+    }
+
+    // Sounds are only synthesized when they can be heard
+    fn audible(&self) -> bool {
+        !self.output.is_null() && !self.muted && self.volume > 0.0
+    }
+
+    fn play(&self, mut samples: Vec<f32>) {
+        for sample in samples.iter_mut() {
+            *sample *= self.volume;
+        }
+        self.output.play(samples);
+    }
+}
+
+// Generate `duration` seconds of sound. The generator gets the time in seconds and the progress from 0 to 1.
+fn synth<F: FnMut(f32, f32) -> f32>(duration: f32, mut generator: F) -> Vec<f32> {
+    let count = (duration * SAMPLE_RATE as f32) as usize;
+    (0..count).map(|i| {
+        let t = i as f32 / SAMPLE_RATE as f32;
+        generator(t, i as f32 / count as f32).max(-1.0).min(1.0)
+    }).collect()
+}
+
+// Notes played one after another, each ringing out until the end
+fn arpeggio(notes: &[f32], step: f32, ring: f32) -> Vec<f32> {
+    let duration = step * (notes.len() - 1) as f32 + ring;
+    let gain = 0.8 / notes.len() as f32;
+    synth(duration, |t, _| {
+        notes.iter().enumerate().map(|(i, &freq)| {
+            let local = t - step * i as f32;
+            if local < 0.0 || local > ring { return 0.0; }
+            let envelope = (1.0 - local / ring).powi(2);
+            (sine(freq * local) + 0.3 * square(freq * local)) * envelope
+        }).sum::<f32>() * gain
+    })
+}
+
+fn semitones(n: f32) -> f32 {
+    2.0f32.powf(n / 12.0)
+}
+
+// Waveforms with a period of 1
+fn sine(phase: f32) -> f32 {
+    (2.0 * PI * phase).sin()
+}
+
+fn square(phase: f32) -> f32 {
+    if phase.fract() < 0.5 { 1.0 } else { -1.0 }
+}
+
+fn triangle(phase: f32) -> f32 {
+    4.0 * (phase.fract() - 0.5).abs() - 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use na::{Point2, Vector2};
+
+    use events::GameEvent;
+    use modes::Outcome;
+    use scoring::ScoreEvent;
+    use super::{Audio, Output};
+
+    // One event of every kind
+    fn every_event() -> Vec<GameEvent> {
+        let score_event = ScoreEvent {
+            lines: 2, base: 20, multi_line_bonus: 10, combo: 1, combo_bonus: 5, pieces: 6, efficiency_bonus: 5,
+        };
+        let events = vec![
+            GameEvent::Spawned { position: Point2::new(6.0, 14.0), color: [1.0, 0.5, 0.0], blocks: 4 },
+            GameEvent::ControlLost,
+            GameEvent::LinesCleared { heights: vec![0.5, 1.5], blocks_removed: 24 },
+            GameEvent::BlockRemoved { position: Point2::new(3.0, 0.5), color: [0.0, 0.5, 1.0] },
+            GameEvent::Split { fragments: 2 },
+            GameEvent::ScoreChanged { score: 40, event: score_event },
+            GameEvent::LevelChanged { level: 2 },
+            GameEvent::GarbageAdded { rows: 1 },
+            GameEvent::Impact { position: Point2::new(6.0, 1.0), normal: Vector2::new(0.0, 1.0), estimated_impulse: 2.0 },
+            GameEvent::Rewound { rewinds: 1 },
+            GameEvent::GameOver { outcome: Outcome::ToppedOut(Duration::from_millis(60_000)) },
+        ];
+        // Fails to compile when there's a new kind of event, which should be added above
+        for event in events.iter() {
+            match *event {
+                GameEvent::Spawned { .. } | GameEvent::ControlLost | GameEvent::LinesCleared { .. } |
+                GameEvent::BlockRemoved { .. } | GameEvent::Split { .. } | GameEvent::ScoreChanged { .. } |
+                GameEvent::LevelChanged { .. } | GameEvent::GarbageAdded { .. } | GameEvent::Impact { .. } |
+                GameEvent::Rewound { .. } | GameEvent::GameOver { .. } => {},
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn handles_every_event_headless() {
+        let mut audio = Audio::new(Output::Null);
+        assert!(!audio.audible());
+        audio.update();
+        for event in every_event() {
+            audio.handle_event(&event);
+            audio.update();
+        }
+        // The game state is followed even though nothing is played
+        assert_eq!(audio.level, 2);
+        assert!(audio.next_bar.is_none());
+        audio.reset();
+        assert!(audio.next_bar.is_some());
+    }
+}
@@ -0,0 +1,90 @@
+// Watches a game that's streamed by another instance, started with `--spectate`.
+// Usage: viewer [address], e.g. viewer 192.168.1.10:7780
+extern crate glium;
+extern crate gliumtetris;
+
+use std::env;
+use std::process;
+use std::time::{Duration, Instant};
+
+use glium::{DisplayBuild, Surface};
+
+use gliumtetris::consts;
+use gliumtetris::graphics::{draw_frame, GraphicsProperties, Viewport};
+use gliumtetris::spectate::Spectator;
+
+const DEFAULT_ADDRESS: &'static str = "127.0.0.1:7780";
+// Window pixels per unit of the well
+const PIXELS_PER_UNIT: f32 = 50.0;
+
+fn main() {
+    let address = env::args().nth(1).unwrap_or(DEFAULT_ADDRESS.to_owned());
+    let mut spectator = match Spectator::connect(&address) {
+        Ok(spectator) => spectator,
+        Err(error) => {
+            println!("{}", error);
+            process::exit(1);
+        },
+    };
+
+    let height = ((consts::TOP - consts::BOTTOM) * PIXELS_PER_UNIT) as u32;
+    let display = glium::glutin::WindowBuilder::new()
+        .with_title(format!("Glium Tetris - watching {}", address))
+        .with_dimensions(((consts::RIGHT - consts::LEFT) * PIXELS_PER_UNIT) as u32, height)
+        .with_multisampling(4)
+        .with_vsync()
+        .build_glium().unwrap();
+    let mut props = GraphicsProperties::new(&display);
+    let mut frame = None;
+    let mut total_width = 0.0;
+    let mut last_update = Instant::now();
+
+    'mainloop: loop {
+        for event in display.poll_events() {
+            use glium::glutin::Event::*;
+            use glium::glutin::ElementState::*;
+            use glium::glutin::VirtualKeyCode::*;
+            match event {
+                Closed | KeyboardInput(Pressed, _, Some(Escape)) => break 'mainloop,
+                _ => {},
+            }
+        }
+
+        if let Some(latest) = spectator.latest() {
+            frame = Some(latest);
+        }
+        if let Some(error) = spectator.error() {
+            println!("{}", error);
+            break 'mainloop;
+        }
+
+        let mut target = display.draw();
+        target.clear_color(0.0, 0.0, 0.0, 1.0);
+        if let Some(ref frame) = frame {
+            // Games are shown side by side, and the window is made to fit them
+            let width: f32 = frame.games.iter().map(|game| game.well_width).sum();
+            if width != total_width {
+                total_width = width;
+                if let Some(window) = display.get_window() {
+                    window.set_inner_size((width * PIXELS_PER_UNIT) as u32, height);
+                }
+            }
+            let mut left = 0.0;
+            for game in frame.games.iter() {
+                props.set_viewport(Viewport { left: left / width, bottom: 0.0, width: game.well_width / width, height: 1.0 });
+                props.set_well_width(game.well_width);
+                game.draw(&display, &mut target, &props, frame.tick);
+                draw_frame(&display, &mut target, &props);
+                left += game.well_width;
+            }
+        }
+        target.finish().unwrap();
+
+        let now = Instant::now();
+        let delta = now - last_update;
+        if delta < Duration::from_millis(consts::TIMESTEP_MS) {
+            ::std::thread::sleep(Duration::from_millis(consts::TIMESTEP_MS) - delta);
+        }
+        last_update = now;
+    }
+}
@@ -0,0 +1,136 @@
+use na::{self, Point2, Vector2};
+
+// Clip a convex polygon against the half-plane of all points p for which dot(normal, p) <= offset
+// (Sutherland-Hodgman, for a single edge). The result is again a convex polygon, with its
+// vertices in the same order as the input.
+pub fn clip_half_plane(poly: &[Point2<f32>], normal: Vector2<f32>, offset: f32) -> Vec<Point2<f32>> {
+    let mut result = vec![];
+    let distance = |pt: &Point2<f32>| na::dot(&normal, &pt.to_vector()) - offset;
+    for (i, p1) in poly.iter().enumerate() {
+        let p2 = &poly[(i + 1) % poly.len()];
+        let (d1, d2) = (distance(p1), distance(p2));
+        if d1 <= 0.0 {
+            result.push(*p1);
+        }
+        if (d1 <= 0.0) != (d2 <= 0.0) {
+            // This edge crosses the boundary
+            let t = d1 / (d1 - d2);
+            result.push(*p1 + (*p2 - *p1) * t);
+        }
+    }
+    result
+}
+
+// The parts of a convex polygon below and above the horizontal band [y_min, y_max]
+pub fn cut_band(poly: &[Point2<f32>], y_min: f32, y_max: f32) -> (Vec<Point2<f32>>, Vec<Point2<f32>>) {
+    (clip_half_plane(poly, Vector2::new(0.0, 1.0), y_min),
+     clip_half_plane(poly, Vector2::new(0.0, -1.0), -y_max))
+}
+
+// Signed area (positive for counterclockwise polygons)
+pub fn area(poly: &[Point2<f32>]) -> f32 {
+    poly.iter().enumerate()
+        .fold(0.0, |sum, (i, p1)| {
+            let p2 = &poly[(i + 1) % poly.len()];
+            sum + p1.x * p2.y - p2.x * p1.y
+        }) / 2.0
+}
+
+pub fn centroid(poly: &[Point2<f32>]) -> Point2<f32> {
+    let a = area(poly);
+    if a.abs() < ::std::f32::EPSILON {
+        // Degenerate polygon: fall back to the average vertex
+        let sum = poly.iter().fold(Vector2::new(0.0, 0.0), |sum, pt| sum + pt.to_vector());
+        return (sum / poly.len() as f32).to_point();
+    }
+    let sum = poly.iter().enumerate()
+        .fold(Vector2::new(0.0, 0.0), |sum, (i, p1)| {
+            let p2 = &poly[(i + 1) % poly.len()];
+            let cross = p1.x * p2.y - p2.x * p1.y;
+            sum + (p1.to_vector() + p2.to_vector()) * cross
+        });
+    (sum / (6.0 * a)).to_point()
+}
+
+// Mass, centroid and moment of inertia around the centroid of a polygon with uniform density
+pub fn mass_properties(poly: &[Point2<f32>], density: f32) -> (f32, Point2<f32>, f32) {
+    let a = area(poly);
+    let center = centroid(poly);
+    // Second moment of area around the origin
+    let moment = poly.iter().enumerate()
+        .fold(0.0, |sum, (i, p1)| {
+            let p2 = &poly[(i + 1) % poly.len()];
+            let cross = p1.x * p2.y - p2.x * p1.y;
+            sum + cross * (p1.x * p1.x + p1.x * p2.x + p2.x * p2.x + p1.y * p1.y + p1.y * p2.y + p2.y * p2.y)
+        }) / 12.0;
+    let mass = density * a.abs();
+    // Both the area and the moment are negative for clockwise polygons
+    let inertia = density * moment * a.signum() - mass * na::dot(&center.to_vector(), &center.to_vector());
+    (mass, center, inertia)
+}
+
+#[cfg(test)]
+mod tests {
+    use na::{Point2, Vector2};
+
+    use super::{area, clip_half_plane, cut_band, mass_properties};
+
+    const EPSILON: f32 = 1e-5;
+
+    fn unit_square() -> Vec<Point2<f32>> {
+        vec![Point2::new(0.0, 0.0), Point2::new(1.0, 0.0), Point2::new(1.0, 1.0), Point2::new(0.0, 1.0)]
+    }
+
+    fn close(a: Point2<f32>, b: Point2<f32>) -> bool {
+        (a.x - b.x).abs() < EPSILON && (a.y - b.y).abs() < EPSILON
+    }
+
+    #[test]
+    fn clipping_a_square() {
+        // The quarter left of x = 0.25
+        let strip = clip_half_plane(&unit_square(), Vector2::new(1.0, 0.0), 0.25);
+        let (mass, center, _) = mass_properties(&strip, 2.0);
+        assert!((area(&strip) - 0.25).abs() < EPSILON);
+        assert!((mass - 0.5).abs() < EPSILON);
+        assert!(close(center, Point2::new(0.125, 0.5)));
+
+        // The triangle below the diagonal x + y = 1
+        let triangle = clip_half_plane(&unit_square(), Vector2::new(1.0, 1.0), 1.0);
+        let (mass, center, _) = mass_properties(&triangle, 1.0);
+        assert!((mass - 0.5).abs() < EPSILON);
+        assert!(close(center, Point2::new(1.0 / 3.0, 1.0 / 3.0)));
+
+        // Nothing, and everything
+        assert!(clip_half_plane(&unit_square(), Vector2::new(1.0, 0.0), -1.0).is_empty());
+        assert_eq!(clip_half_plane(&unit_square(), Vector2::new(1.0, 0.0), 2.0), unit_square());
+    }
+
+    #[test]
+    fn mass_properties_of_a_square() {
+        let mut square = unit_square();
+        let (mass, center, inertia) = mass_properties(&square, 2.0);
+        assert!((mass - 2.0).abs() < EPSILON);
+        assert!(close(center, Point2::new(0.5, 0.5)));
+        assert!((inertia - mass * 2.0 / 12.0).abs() < EPSILON);
+
+        // The winding order doesn't matter
+        square.reverse();
+        let (reversed_mass, reversed_center, reversed_inertia) = mass_properties(&square, 2.0);
+        assert!((reversed_mass - mass).abs() < EPSILON);
+        assert!(close(reversed_center, center));
+        assert!((reversed_inertia - inertia).abs() < EPSILON);
+    }
+
+    #[test]
+    fn cutting_a_band_keeps_the_rest_of_the_area() {
+        let diamond = vec![Point2::new(0.0, -1.0), Point2::new(1.0, 0.0), Point2::new(0.0, 1.0), Point2::new(-1.0, 0.0)];
+        for (poly, y_min, y_max) in vec![(unit_square(), 0.3, 0.55), (diamond, -0.2, 0.4)] {
+            let (below, above) = cut_band(&poly, y_min, y_max);
+            let band = clip_half_plane(&clip_half_plane(&poly, Vector2::new(0.0, -1.0), -y_min),
+                                       Vector2::new(0.0, 1.0), y_max);
+            assert!(below.iter().all(|pt| pt.y <= y_min + EPSILON));
+            assert!(above.iter().all(|pt| pt.y >= y_max - EPSILON));
+            assert!((area(&below) + area(&band) + area(&above) - area(&poly)).abs() < EPSILON);
+        }
+    }
+}
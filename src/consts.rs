@@ -1,5 +1,7 @@
 use na::Vector2;
 
+use levels::Level;
+
 pub const GRAVITY: Vector2<f32> = Vector2 { x: 0.0, y: -20.0 };
 
 pub const WALL_RESTITUTION: f32 = 0.4;
@@ -19,14 +21,78 @@ pub const TOP: f32 = 16.0;
 
 pub const LINE_THRESHOLD: f32 = 0.1;
 pub const BLOCKS_PER_LINE: usize = 12;
+// The shared well of a co-op game is wider
+pub const COOP_BLOCKS_PER_LINE: usize = 20;
+pub const COLUMN_WIDTH: f32 = (RIGHT - LEFT) / BLOCKS_PER_LINE as f32;
+// Fraction of a band that needs to be covered for the area coverage line detector
+pub const DEFAULT_LINE_COVERAGE: f32 = 0.85;
+pub const COVERAGE_SCANLINES: usize = 8;
 
 pub const BLOCK_DIST: f32 = 0.995 * (RIGHT - LEFT) / BLOCKS_PER_LINE as f32;
 pub const BLOCK_SIZE: f32 = 0.96 * BLOCK_DIST;
 pub const CORNER_RADIUS: f32 = 0.16 * BLOCK_SIZE;
 pub const EDGES_PER_CORNER: u32 = 3;
-pub const VERTS_PER_BLOCK: u32 = 4 * (EDGES_PER_CORNER + 1);
+// Fragments of cut blocks smaller than this are discarded
+pub const MIN_FRAGMENT_AREA: f32 = 0.02 * BLOCK_SIZE * BLOCK_SIZE;
+// Blocks of an object stay connected while they're closer together than this. Neighbouring blocks
+// are BLOCK_DIST - BLOCK_SIZE apart.
+pub const CONNECTED_DISTANCE: f32 = 1.5 * (BLOCK_DIST - BLOCK_SIZE);
+// Duration of the animation of blocks disappearing from a cleared line
+pub const CLEAR_ANIMATION_MS: u64 = 400;
+// Contacts with an estimated impulse (approach speed times reduced mass) larger than this are impacts
+pub const IMPACT_ESTIMATED_IMPULSE: f32 = 0.5;
+// Number of physics steps the trajectory prediction looks ahead
+pub const PREDICTION_TICKS: usize = 90;
+// Objects closer than this to the controlled object are taken into account by the prediction
+pub const PREDICTION_RADIUS: f32 = 6.0;
+
+pub const DEFAULT_VOLUME: f32 = 0.7;
+pub const VOLUME_STEP: f32 = 0.1;
 
 pub const SPAWN_DELAY_MS: u64 = 750;
+// Number of spawns that can be undone
+pub const REWIND_HISTORY: usize = 10;
+
+// Duration of a single physics step
+pub const TIMESTEP_MS: u64 = 16;
+pub const TIMESTEP: f32 = TIMESTEP_MS as f32 / 1000.0;
+
+pub const LINE_SCORE: usize = 10;
+pub const COMBO_WINDOW_MS: u64 = 4000;
+pub const COMBO_PERCENT: usize = 50;
+pub const EFFICIENCY_SCORE: usize = 5;
+pub const SCORE_DISPLAY_MS: u64 = 2500;
+
+pub const COUNTDOWN_MS: u64 = 3000;
+pub const SPRINT_LINES: usize = 40;
+pub const ULTRA_TIME_MS: u64 = 120_000;
+// After the time limit, the game ends once every object moves slower than this
+pub const SETTLE_SPEED: f32 = 0.05;
+pub const SETTLE_TIMEOUT_MS: u64 = 5000;
+pub const HIGH_SCORE_ENTRIES: usize = 10;
+
+pub const GARBAGE_INTERVAL_MS: u64 = 12000;
+pub const GARBAGE_DENSITY: f32 = 0.5;
+pub const GARBAGE_COLOR: [f32; 3] = [0.45, 0.45, 0.45];
+// A resting object with a block above this height ends the game
+pub const TOP_OUT_HEIGHT: f32 = TOP - 4.0 * BLOCK_DIST;
+pub const TOP_OUT_SPEED: f32 = 0.5;
+
+// Garbage rows sent to the opponent in a versus game, by the number of lines cleared at once
+pub const VERSUS_GARBAGE: [usize; 5] = [0, 0, 1, 2, 4];
+
+pub const LEVELS: [Level; 10] = [
+    Level { lines: 0,  gravity: 20.0, auto_spawn_ms: 10000 },
+    Level { lines: 4,  gravity: 22.0, auto_spawn_ms: 8500 },
+    Level { lines: 8,  gravity: 24.0, auto_spawn_ms: 7000 },
+    Level { lines: 12, gravity: 26.5, auto_spawn_ms: 6000 },
+    Level { lines: 16, gravity: 29.0, auto_spawn_ms: 5000 },
+    Level { lines: 20, gravity: 32.0, auto_spawn_ms: 4250 },
+    Level { lines: 25, gravity: 35.0, auto_spawn_ms: 3500 },
+    Level { lines: 30, gravity: 38.5, auto_spawn_ms: 3000 },
+    Level { lines: 35, gravity: 42.0, auto_spawn_ms: 2500 },
+    Level { lines: 40, gravity: 46.0, auto_spawn_ms: 2000 },
+];
 
 pub use self::polyominos::POLYOMINOS;
 
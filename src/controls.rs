@@ -1,17 +1,75 @@
 use glium::glutin::VirtualKeyCode;
 use glium::glutin::VirtualKeyCode::*;
 
-pub struct Controls;
+use modes::Mode;
 
+// A set of keys. The left and right keysets only control pieces, so two players can share a keyboard.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Controls {
+    Single,
+    Left,
+    Right,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Action {
     RotateCW, RotateCCW, RotateStop,
     MoveLeft, MoveRight, MoveStop,
     TrySpawn,
+    Rewind,
     GameReset,
+    SelectMode(Mode),
+    ToggleLineDetector,
+    ToggleSplitMode,
+}
+
+// Keys that only change the frontend: how the game is presented, the sound, and whether the bot plays.
+// These never reach the game.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Command {
+    ToggleParticles,
+    ToggleGhost,
+    TogglePrediction,
+    ToggleDebug,
+    ToggleMute,
+    VolumeUp,
+    VolumeDown,
+    ToggleBot,
+}
+
+impl Action {
+    // True for the actions that control the pieces, rather than the game as a whole
+    pub fn is_piece_control(&self) -> bool {
+        match *self {
+            Action::RotateCW | Action::RotateCCW | Action::RotateStop |
+            Action::MoveLeft | Action::MoveRight | Action::MoveStop |
+            Action::TrySpawn => true,
+            _ => false,
+        }
+    }
 }
 
 impl Controls {
     pub fn resolve_press(&self, key: VirtualKeyCode) -> Option<Action> {
+        match *self {
+            Controls::Single => {},
+            Controls::Left => return Some(match key {
+                W      => Action::RotateCW,
+                S      => Action::RotateCCW,
+                A      => Action::MoveLeft,
+                D      => Action::MoveRight,
+                LShift => Action::TrySpawn,
+                _      => return None,
+            }),
+            Controls::Right => return Some(match key {
+                Up     => Action::RotateCW,
+                Down   => Action::RotateCCW,
+                Left   => Action::MoveLeft,
+                Right  => Action::MoveRight,
+                RShift => Action::TrySpawn,
+                _      => return None,
+            }),
+        }
         Some(match key {
             Up | K    => Action::RotateCW,
             Down | J  => Action::RotateCCW,
@@ -19,11 +77,47 @@ impl Controls {
             Right | L => Action::MoveRight,
             Space     => Action::TrySpawn,
             Back      => Action::GameReset,
+            R         => Action::Rewind,
+            F1        => Action::SelectMode(Mode::Marathon),
+            F2        => Action::SelectMode(Mode::Sprint),
+            F3        => Action::SelectMode(Mode::Ultra),
+            F4        => Action::SelectMode(Mode::Survival),
+            F5        => Action::ToggleLineDetector,
+            F6        => Action::ToggleSplitMode,
             _         => return None
         })
     }
 
+    // Frontend commands are only bound in the single player keyset
+    pub fn resolve_command(&self, key: VirtualKeyCode) -> Option<Command> {
+        if *self != Controls::Single { return None; }
+        Some(match key {
+            F7     => Command::ToggleParticles,
+            F8     => Command::ToggleGhost,
+            F9     => Command::TogglePrediction,
+            F10    => Command::ToggleDebug,
+            F11    => Command::ToggleBot,
+            M      => Command::ToggleMute,
+            Equals => Command::VolumeUp,
+            Minus  => Command::VolumeDown,
+            _      => return None
+        })
+    }
+
     pub fn resolve_release(&self, key: VirtualKeyCode) -> Option<Action> {
+        match *self {
+            Controls::Single => {},
+            Controls::Left => return Some(match key {
+                W | S => Action::RotateStop,
+                A | D => Action::MoveStop,
+                _     => return None,
+            }),
+            Controls::Right => return Some(match key {
+                Up | Down    => Action::RotateStop,
+                Left | Right => Action::MoveStop,
+                _            => return None,
+            }),
+        }
         Some(match key {
             Up | Down | K | J    => Action::RotateStop,
             Left | H | Right | L => Action::MoveStop,
@@ -0,0 +1,264 @@
+use na::{Point2, Rotation};
+
+use consts::*;
+use controls::Action;
+use events::GameEvent;
+use game::{block_outline, Game, Move, RotateMove};
+use lines::hull_interval;
+use modes::Mode;
+
+// What the agent gets to see
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ObservationKind {
+    Grid,
+    Bodies,
+    Both,
+}
+
+pub struct EnvConfig {
+    pub observation: ObservationKind,
+    // Number of physics steps per environment step
+    pub ticks_per_step: usize,
+    // Number of grid cells per block, in both directions
+    pub grid_resolution: usize,
+    pub mode: Mode,
+    // Episodes are cut off after this many steps
+    pub max_steps: Option<usize>,
+}
+
+impl EnvConfig {
+    pub fn new() -> Self {
+        EnvConfig {
+            observation: ObservationKind::Both,
+            ticks_per_step: 4,
+            grid_resolution: 2,
+            mode: Mode::Marathon,
+            max_steps: None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct BodyState {
+    pub position: Point2<f32>,
+    pub angle: f32,
+    pub velocity: [f32; 2],
+    pub angular_velocity: f32,
+    pub blocks: usize,
+    pub controlled: bool,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Observation {
+    // Occupancy of the well, row by row from the bottom up: 0 is empty, 1 is a block that's not
+    // controlled, and 2 is a block of the controlled object.
+    pub grid: Option<Vec<u8>>,
+    pub grid_width: usize,
+    pub grid_height: usize,
+    // Every object in the well, in the order they were added
+    pub bodies: Option<Vec<BodyState>>,
+}
+
+// The controls held during a step, and whether to try to spawn the next piece.
+#[derive(Copy, Clone)]
+pub struct EnvAction {
+    pub rotate: Option<RotateMove>,
+    pub mov: Option<Move>,
+    pub spawn: bool,
+}
+
+// Number of distinct actions, for agents with a discrete action space
+pub const ACTION_COUNT: usize = 18;
+
+impl EnvAction {
+    pub fn idle() -> Self {
+        EnvAction { rotate: None, mov: None, spawn: false }
+    }
+
+    // The action with the given index, from 0 up to ACTION_COUNT
+    pub fn from_index(index: usize) -> Self {
+        assert!(index < ACTION_COUNT, "action index out of range: {}", index);
+        EnvAction {
+            rotate: [None, Some(RotateMove::Clockwise), Some(RotateMove::Counterclockwise)][index % 3],
+            mov: [None, Some(Move::Left), Some(Move::Right)][index / 3 % 3],
+            spawn: index / 9 == 1,
+        }
+    }
+}
+
+// A gym-style environment over `Game`, for training agents. It runs headless, and an episode only
+// depends on its seed and the actions taken.
+pub struct Env {
+    game: Game,
+    config: EnvConfig,
+    steps: usize,
+    last_score: usize,
+    events: Vec<GameEvent>,
+}
+
+impl Env {
+    pub fn new(config: EnvConfig) -> Self {
+        let mut game = Game::with_seed(0);
+        game.set_mode(config.mode);
+        // Agents can't rewind, so the game doesn't need snapshots
+        game.set_rewind_history(0);
+        Env {
+            game: game,
+            config: config,
+            steps: 0,
+            last_score: 0,
+            events: vec![],
+        }
+    }
+
+    pub fn game(&self) -> &Game { &self.game }
+    pub fn steps(&self) -> usize { self.steps }
+
+    // The events that happened during the last step
+    pub fn events(&self) -> &[GameEvent] { &self.events }
+
+    // Start a new episode
+    pub fn reset(&mut self, seed: u32) -> Observation {
+        self.game.set_mode(self.config.mode);
+        self.game.reseed(seed);
+        // Agents don't need to wait for the countdown
+        while self.game.countdown().is_some() {
+            self.game.update();
+        }
+        self.game.drain_events().count();
+        self.steps = 0;
+        self.last_score = 0;
+        self.events.clear();
+        self.observe()
+    }
+
+    // Apply an action, and advance the game by `ticks_per_step` physics steps.
+    // The reward is the score gained during the step.
+    pub fn step(&mut self, action: EnvAction) -> (Observation, f32, bool) {
+        self.game.execute_action(match action.rotate {
+            Some(RotateMove::Clockwise) => Action::RotateCW,
+            Some(RotateMove::Counterclockwise) => Action::RotateCCW,
+            None => Action::RotateStop,
+        });
+        self.game.execute_action(match action.mov {
+            Some(Move::Left) => Action::MoveLeft,
+            Some(Move::Right) => Action::MoveRight,
+            None => Action::MoveStop,
+        });
+        if action.spawn {
+            self.game.execute_action(Action::TrySpawn);
+        }
+
+        for _ in 0..self.config.ticks_per_step {
+            if self.game.outcome().is_some() { break; }
+            self.game.update();
+        }
+        self.events = self.game.drain_events().collect();
+        self.steps += 1;
+
+        let score = self.game.score();
+        let reward = (score - self.last_score) as f32;
+        self.last_score = score;
+        // Modes without an end of their own (marathon) end when the well tops out
+        let done = self.game.outcome().is_some() || self.game.topped_out() ||
+            self.config.max_steps.map_or(false, |max_steps| self.steps >= max_steps);
+        (self.observe(), reward, done)
+    }
+
+    pub fn observe(&self) -> Observation {
+        let kind = self.config.observation;
+        let with_grid = kind == ObservationKind::Grid || kind == ObservationKind::Both;
+        let with_bodies = kind == ObservationKind::Bodies || kind == ObservationKind::Both;
+        let width = self.game.columns() * self.config.grid_resolution;
+        // Grid cells are square
+        let height = ((TOP - BOTTOM) / (self.game.well_right() - LEFT) * width as f32).round() as usize;
+        Observation {
+            grid: if with_grid { Some(self.rasterize(width, height)) } else { None },
+            grid_width: width,
+            grid_height: height,
+            bodies: if with_bodies { Some(self.bodies()) } else { None },
+        }
+    }
+
+    // A cell is occupied if its center is inside a block
+    fn rasterize(&self, width: usize, height: usize) -> Vec<u8> {
+        let mut grid = vec![0; width * height];
+        let cell_width = (self.game.well_right() - LEFT) / width as f32;
+        let cell_height = (TOP - BOTTOM) / height as f32;
+        let controlled = self.game.control_object().map(|obj| &*obj.rbh as *const _);
+        for tetr in self.game.tetrominos() {
+            let value = if Some(&*tetr.rbh as *const _) == controlled { 2 } else { 1 };
+            let iso = *tetr.rbh.borrow().position();
+            for block in tetr.blocks().iter() {
+                let hull: Vec<_> = block_outline(block).into_iter().map(|pt| iso * pt).collect();
+                for row in 0..height {
+                    let y = BOTTOM + (row as f32 + 0.5) * cell_height;
+                    let (x1, x2) = match hull_interval(&hull, y) {
+                        Some(interval) => interval,
+                        None => continue,
+                    };
+                    // The columns with their center in the interval
+                    let first = ((x1 - LEFT) / cell_width - 0.5).ceil().max(0.0) as usize;
+                    let last = ((x2 - LEFT) / cell_width - 0.5).floor();
+                    if last < 0.0 { continue; }
+                    for column in first..::std::cmp::min(last as usize + 1, width) {
+                        grid[row * width + column] = value;
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    fn bodies(&self) -> Vec<BodyState> {
+        let controlled = self.game.control_object().map(|obj| &*obj.rbh as *const _);
+        self.game.tetrominos().map(|tetr| {
+            let rb = tetr.rbh.borrow();
+            BodyState {
+                position: *rb.center_of_mass(),
+                angle: rb.position().rotation().x,
+                velocity: [rb.lin_vel().x, rb.lin_vel().y],
+                angular_velocity: rb.ang_vel().x,
+                blocks: tetr.blocks().len(),
+                controlled: Some(&*tetr.rbh as *const _) == controlled,
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    use super::{Env, EnvAction, EnvConfig, ACTION_COUNT};
+
+    const STEPS: usize = 600;
+
+    fn env() -> Env {
+        let mut config = EnvConfig::new();
+        config.max_steps = Some(STEPS);
+        Env::new(config)
+    }
+
+    #[test]
+    fn same_seed_and_actions_give_the_same_episode() {
+        let (mut first, mut second) = (env(), env());
+        // Whatever happened in an earlier episode doesn't matter
+        second.reset(3);
+        for index in 0..ACTION_COUNT {
+            second.step(EnvAction::from_index(index));
+        }
+
+        assert_eq!(first.reset(7), second.reset(7));
+        let mut rng = XorShiftRng::from_seed([7, 11, 13, 17]);
+        for _ in 0..STEPS {
+            let action = EnvAction::from_index(rng.gen_range(0, ACTION_COUNT));
+            let (observation, reward, done) = first.step(action);
+            let (other_observation, other_reward, other_done) = second.step(action);
+            assert_eq!(observation, other_observation, "observations differ at step {}", first.steps());
+            assert_eq!(reward, other_reward, "rewards differ at step {}", first.steps());
+            assert_eq!(done, other_done);
+            if done { break; }
+        }
+    }
+}
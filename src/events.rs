@@ -0,0 +1,30 @@
+use na::{Point2, Vector2};
+
+use modes::Outcome;
+use scoring::ScoreEvent;
+
+// Something that happened in the game. The game core queues these up during `Game::update`, and
+// the frontend drains them every frame.
+#[derive(Clone, Debug)]
+pub enum GameEvent {
+    // A new polyomino was spawned, and the player now controls it
+    Spawned { position: Point2<f32>, color: [f32; 3], blocks: usize },
+    // The player lost control of the object they were controlling
+    ControlLost,
+    // Lines were completed; their blocks start disappearing
+    LinesCleared { heights: Vec<f32>, blocks_removed: usize },
+    // A block disappeared from a cleared line
+    BlockRemoved { position: Point2<f32>, color: [f32; 3] },
+    // An object was split along a cleared line into a number of fragments
+    Split { fragments: usize },
+    ScoreChanged { score: usize, event: ScoreEvent },
+    LevelChanged { level: usize },
+    // A row of garbage was pushed up from the bottom of the well
+    GarbageAdded { rows: usize },
+    // Two objects hit each other hard. The impulse is estimated from how fast they approach each
+    // other and their masses, ignoring rotation, so it's not the impulse the solver applies.
+    Impact { position: Point2<f32>, normal: Vector2<f32>, estimated_impulse: f32 },
+    // The game was restored to just before the last piece was spawned
+    Rewound { rewinds: usize },
+    GameOver { outcome: Outcome },
+}
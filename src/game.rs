@@ -1,26 +1,91 @@
-use std::time::{Instant, Duration};
+use std::collections::VecDeque;
+use std::mem;
+use std::time::Duration;
 
 use na::{Vector1, Point2, Vector2, Isometry2};
-use na::{Norm, Rotate};
+use na::{Norm, Rotate, Transform};
+use ncollide::query;
 use ncollide::shape::{self, ShapeHandle};
+use nphysics2d::detection::constraint::Constraint;
 use nphysics2d::object::{RigidBody, RigidBodyHandle};
 use nphysics2d::world::World;
 
-use rand;
+use rand::{self, Rng, SeedableRng, XorShiftRng};
 use rand::distributions::{IndependentSample, Range};
 
+use clipping;
 use consts::*;
 use controls::Action;
+use events::GameEvent;
+use levels::Levels;
+use lines::LineDetector;
+use modes::{Mode, Outcome};
+use scoring::{Scoring, ScoreEvent};
+use verify;
 
 #[derive(Clone)]
 pub struct Tetromino {
     pub rbh: RigidBodyHandle<f32>,
     pub color: [f32; 3],
+    pub density: f32,
+    // Grid coordinates of each block in the original polyomino, in the same order as the blocks.
+    // Fragments of a cut block keep the coordinates of the block they came from.
+    pub cells: Vec<[i32; 2]>,
+    // State of each block, in the same order as the blocks
+    pub states: Vec<BlockState>,
+    // The game time at which the piece was spawned, if it was spawned by a player.
+    // Fragments keep the spawn time of the object they came from.
+    pub spawned: Option<Duration>,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BlockState {
+    Normal,
+    // Part of a line that's being cleared
+    Clearing,
 }
 
 pub type Block = (Isometry2<f32>, ShapeHandle<Point2<f32>, Isometry2<f32>>);
 
 impl Tetromino {
+    // Create a new polyomino with the given rotation, with its center of mass at the given position.
+    pub fn new(world: &mut World<f32>, polyomino: &[[usize; 2]], rotation: f32, position: Vector2<f32>, color: [f32; 3]) -> Self {
+        // Reference to the shape of a single block
+        let block_shape = ShapeHandle::new(shape::ConvexHull::new(block(BLOCK_SIZE/2.0, CORNER_RADIUS, EDGES_PER_CORNER)));
+        // let block_shape = ShapeHandle::new(shape::Cuboid::new(Vector2::new(BLOCK_SIZE/2.0, BLOCK_SIZE/2.0)));
+        // Description of all the blocks in a tetromino
+        let cuboids: Vec<_> = polyomino.iter()
+            // Each polyomino is described as a list of pairs of integers, describing the blocks in
+            // the plane that are part of the polyomino.
+            .map(|&[x, y]| {
+                let translation = BLOCK_DIST * Vector2::new(x as f32, y as f32);
+                let transform = Isometry2::new(translation, Vector1::new(0.0));
+                (transform, block_shape.clone())
+            }).collect();
+        // The tetromino shape
+        let total_shape = shape::Compound::new(cuboids);
+
+        let mut rb = RigidBody::new_dynamic(total_shape, POLYOMINO_DENSITY, POLYOMINO_RESTITUTION, POLYOMINO_FRICTION);
+        rb.append_rotation(&Vector1::new(rotation));
+        // Normalize position by moving the center of mass to the origin
+        let com = -rb.center_of_mass().to_vector();
+        rb.append_translation(&com);
+        rb.append_translation(&position);
+        // Never deactivate a tetromino
+        rb.set_deactivation_threshold(None);
+        // Set a small margin
+        rb.set_margin(0.012);
+
+        Tetromino {
+            rbh: world.add_rigid_body(rb),
+            color: color,
+            density: POLYOMINO_DENSITY,
+            cells: polyomino.iter().map(|&[x, y]| [x as i32, y as i32]).collect(),
+            states: vec![BlockState::Normal; polyomino.len()],
+            spawned: None,
+        }
+    }
+
     pub fn blocks(&self) -> Vec<Block> {
         self.rbh.borrow()
             .shape().as_shape::<shape::Compound<_, _>>().unwrap()
@@ -28,47 +93,113 @@ impl Tetromino {
             .collect()
     }
 
-    fn retained_blocks(&self, y_pos: f32, mut threshold: f32) -> Vec<Block> {
-        threshold *= 1.01; // meh
+    // The blocks that remain after clearing the band [y_pos - threshold, y_pos + threshold],
+    // together with their grid coordinates, divided into sets of blocks that can never be
+    // connected to each other.
+    fn retained_blocks(&self, y_pos: f32, mut threshold: f32, mode: SplitMode) -> Vec<Vec<(Block, [i32; 2])>> {
         let orig_iso = *self.rbh.borrow().position();
-        self.blocks().into_iter()
-            .filter(|&(iso, _)| {
-                let y = (orig_iso.translation + orig_iso.rotation.rotate(&iso.translation)).y;
-                (y - y_pos).abs() > threshold
-            }).collect()
+        let blocks = self.blocks().into_iter().zip(self.cells.iter().cloned());
+        match mode {
+            SplitMode::Remove => {
+                threshold *= 1.01; // meh
+                vec![blocks
+                    .filter(|&((iso, _), _)| {
+                        let y = (orig_iso.translation + orig_iso.rotation.rotate(&iso.translation)).y;
+                        (y - y_pos).abs() > threshold
+                    }).collect()]
+            },
+            SplitMode::Cut => {
+                let (y_min, y_max) = (y_pos - threshold, y_pos + threshold);
+                let (mut below, mut above) = (vec![], vec![]);
+                for (block, cell) in blocks {
+                    let outline: Vec<_> = block_outline(&block).into_iter().map(|pt| orig_iso * pt).collect();
+                    // Blocks that don't touch the band are kept as they are
+                    if outline.iter().all(|pt| pt.y <= y_min) {
+                        below.push((block, cell));
+                        continue;
+                    }
+                    if outline.iter().all(|pt| pt.y >= y_max) {
+                        above.push((block, cell));
+                        continue;
+                    }
+                    let (part_below, part_above) = clipping::cut_band(&outline, y_min, y_max);
+                    for (part, side) in vec![(part_below, &mut below), (part_above, &mut above)] {
+                        if part.len() < 3 || clipping::area(&part).abs() < MIN_FRAGMENT_AREA {
+                            continue;
+                        }
+                        // Express the fragment relative to its own centroid, in the frame of this object
+                        let local: Vec<_> = part.iter().map(|pt| orig_iso.inverse_transform(pt)).collect();
+                        let center = clipping::centroid(&local);
+                        let points: Vec<_> = local.iter().map(|&pt| (pt - center).to_point()).collect();
+                        side.push(((Isometry2::new(center.to_vector(), Vector1::new(0.0)),
+                                    ShapeHandle::new(shape::ConvexHull::new(points))),
+                                   cell));
+                    }
+                }
+                vec![below, above]
+            },
+        }
+    }
+
+    // For each block, whether it is (partially) removed by clearing the given band
+    pub fn blocks_in_band(&self, y_pos: f32, threshold: f32, mode: SplitMode) -> Vec<bool> {
+        let orig_iso = *self.rbh.borrow().position();
+        self.blocks().iter().map(|block| match mode {
+            SplitMode::Remove => {
+                let y = (orig_iso.translation + orig_iso.rotation.rotate(&block.0.translation)).y;
+                (y - y_pos).abs() <= threshold * 1.01
+            },
+            SplitMode::Cut => {
+                let heights: Vec<f32> = block_outline(block).into_iter().map(|pt| (orig_iso * pt).y).collect();
+                heights.iter().any(|&y| y < y_pos + threshold) && heights.iter().any(|&y| y > y_pos - threshold)
+            },
+        }).collect()
+    }
+
+    // A new dynamic body with the same shape, pose and velocity as this object
+    pub fn clone_body(&self) -> RigidBody<f32> {
+        let rb = self.rbh.borrow();
+        let mut copy = RigidBody::new_dynamic(shape::Compound::new(self.blocks()), self.density, POLYOMINO_RESTITUTION, POLYOMINO_FRICTION);
+        copy.set_transformation(*rb.position());
+        copy.set_lin_vel(rb.lin_vel());
+        copy.set_ang_vel(rb.ang_vel());
+        copy.set_deactivation_threshold(None);
+        copy.set_margin(0.012);
+        copy
+    }
+
+    // A new static body with the same shape and pose as this object
+    pub fn clone_static_body(&self) -> RigidBody<f32> {
+        let mut copy = RigidBody::new_static(shape::Compound::new(self.blocks()), POLYOMINO_RESTITUTION, POLYOMINO_FRICTION);
+        copy.set_transformation(*self.rbh.borrow().position());
+        copy.set_margin(0.012);
+        copy
     }
 
-    pub fn requires_split(&self, y_pos: f32, threshold: f32) -> bool {
-        self.retained_blocks(y_pos, threshold).len() != self.blocks().len()
+    pub fn requires_split(&self, y_pos: f32, threshold: f32, mode: SplitMode) -> bool {
+        self.blocks_in_band(y_pos, threshold, mode).contains(&true)
     }
 
-    pub fn split_blocks(self, world: &mut World<f32>, y_pos: f32, threshold: f32) -> Vec<Self> {
+    pub fn split_blocks(self, world: &mut World<f32>, y_pos: f32, threshold: f32, mode: SplitMode) -> Vec<Self> {
         // Save the physical characteristics of the original object
         let (orig_iso, orig_lin_vel, orig_ang_vel, orig_com) = {
             let rb = self.rbh.borrow();
             (*rb.position(), rb.lin_vel(), rb.ang_vel(), *rb.center_of_mass())
         };
 
-        self.retained_blocks(y_pos, threshold).into_iter()
-            // Transform the list of remaining blocks into a forest of disjoint groups of blocks
-            .fold(vec![], |mut block_forest: Vec<Vec<Block>>, (iso, shape): Block| -> Vec<Vec<Block>>{
-                let (adjacent, mut others): (Vec<_>, Vec<_>) = block_forest.drain(..)
-                    .partition(|block_group: &Vec<Block>|
-                        block_group.iter().any(|&(other_iso, _)| {
-                            (other_iso.translation - iso.translation).norm() < BLOCK_SIZE * 1.3
-                        })
-                    );
-                let mut block_group: Vec<Block> = adjacent.into_iter().flat_map(|v| v.into_iter()).collect();
-                block_group.push((iso, shape));
-
-                others.push(block_group);
-
-                others
-            }).into_iter()
+        self.retained_blocks(y_pos, threshold, mode).into_iter()
+            // Divide each set of remaining blocks into groups of connected blocks
+            .flat_map(|blocks| {
+                let connected = |i: usize, j: usize| blocks_connected(&blocks[i].0, blocks[i].1, &blocks[j].0, blocks[j].1);
+                connected_components(blocks.len(), connected).into_iter()
+                    .map(|component| component.into_iter().map(|i| blocks[i].clone()).collect::<Vec<_>>())
+                    .collect::<Vec<_>>().into_iter()
+            })
             // For each group of blocks, construct a new tetromino
-            .map(|block_group: Vec<Block>| {
+            .map(|block_group: Vec<(Block, [i32; 2])>| {
+                let (block_group, cells): (Vec<Block>, Vec<[i32; 2]>) = block_group.into_iter().unzip();
                 let total_shape = shape::Compound::new(block_group);
-                let mut rb = RigidBody::new_dynamic(total_shape, POLYOMINO_DENSITY, POLYOMINO_RESTITUTION, POLYOMINO_FRICTION);
+                let mut rb = RigidBody::new_dynamic(total_shape, self.density, POLYOMINO_RESTITUTION, POLYOMINO_FRICTION);
                 // Compute the position, rotation, linear velocity and angular velocity
                 // of the newly generated object
                 // The transform of the new object is equal to the transform of its generator.
@@ -94,209 +225,906 @@ impl Tetromino {
                 Tetromino {
                     rbh: world.add_rigid_body(rb),
                     color: self.color,
+                    density: self.density,
+                    states: vec![BlockState::Normal; cells.len()],
+                    cells: cells,
+                    spawned: self.spawned,
                 }
             }).collect()
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum RotateMove { Clockwise, Counterclockwise }
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Move { Left, Right, }
+// Lines that are being cleared, while the affected blocks are shown disappearing
+#[derive(Clone)]
+struct Clearing {
+    started: Duration,
+    lines: Vec<(f32, f32)>,
+    // The affected objects are replaced by static copies, which hold them in place without pushing
+    // their neighbours around until the lines are removed
+    frozen: Vec<RigidBodyHandle<f32>>,
+}
 
-pub struct Game {
-    world: World<f32>,
-    objects: Vec<Tetromino>,
+// The piece a player controls, and the controls they're holding
+#[derive(Clone)]
+struct Player {
     control_object: Option<Tetromino>,
     rotate: Option<RotateMove>,
     mov: Option<Move>,
-    score: usize,
-    last_spawn: Option<Instant>,
-    last_score: Option<Instant>,
+    last_spawn: Option<Duration>,
 }
 
-impl Game {
-    pub fn new() -> Self {
-        Game {
-            world: create_world(),
-            objects: vec![],
+impl Player {
+    fn new() -> Self {
+        Player {
             control_object: None,
             rotate: None,
             mov: None,
-            score: 0,
             last_spawn: None,
-            last_score: None,
         }
     }
+}
 
-    pub fn add_tetromino(&mut self) {
-        self.last_spawn = Some(Instant::now());
-        let mut rng = rand::thread_rng();
+// What happens to blocks that are partially in a cleared line
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SplitMode {
+    // Blocks with their center in the line are removed completely
+    Remove,
+    // Blocks are cut along the edges of the line, and only the parts in the line are removed
+    Cut,
+}
 
-        // Reference to the shape of a single block
+pub struct Game {
+    world: World<f32>,
+    objects: Vec<Tetromino>,
+    // Every player controls their own piece, in the same well
+    players: Vec<Player>,
+    // Width of the well, in blocks
+    columns: usize,
+    scoring: Scoring,
+    levels: Levels,
+    line_detector: LineDetector,
+    // The fraction of a band the area coverage detector needs covered, when it's switched on
+    line_coverage: f32,
+    split_mode: SplitMode,
+    clearing: Option<Clearing>,
+    clear_duration: Duration,
+    events: VecDeque<GameEvent>,
+    contacts: Vec<(Point2<f32>, Vector2<f32>)>,
+    mode: Mode,
+    // Number of physics steps since the game was reset. All times below are game times,
+    // derived from this, so that a game only depends on its seed and inputs.
+    ticks: u64,
+    // Number of pieces spawned since the game was reset
+    spawned: usize,
+    seed: u32,
+    rng: XorShiftRng,
+    // The moment play starts (after the countdown)
+    started: Duration,
+    last_garbage: Option<Duration>,
+    // The moment the time limit ran out, if it did
+    time_up: Option<Duration>,
+    finished: Option<(Duration, Outcome)>,
+    // Snapshots of the game from just before each of the last few spawns, the latest one last
+    history: VecDeque<Game>,
+    // Number of spawns that can be undone
+    rewind_history: usize,
+    rewinds: usize,
+    // True until the physics world is stepped after it was built from scratch. Until then it
+    // doesn't hold any contact or solver state, and rebuilding it changes nothing.
+    fresh_world: bool,
+}
+
+impl Game {
+    // A new game with a random seed
+    pub fn new() -> Self {
+        Game::with_seed(rand::thread_rng().gen())
+    }
+
+    // A new game that plays out identically for the same seed and inputs
+    pub fn with_seed(seed: u32) -> Self {
+        let mut game = Game {
+            world: create_world(RIGHT),
+            objects: vec![],
+            players: vec![Player::new()],
+            columns: BLOCKS_PER_LINE,
+            scoring: Scoring::new(),
+            levels: Levels::new(),
+            line_detector: LineDetector::BlockCenters,
+            line_coverage: DEFAULT_LINE_COVERAGE,
+            split_mode: SplitMode::Remove,
+            clearing: None,
+            clear_duration: Duration::from_millis(CLEAR_ANIMATION_MS),
+            events: VecDeque::new(),
+            contacts: vec![],
+            mode: Mode::Marathon,
+            ticks: 0,
+            spawned: 0,
+            seed: 0,
+            rng: seeded_rng(0),
+            started: Duration::from_millis(0),
+            last_garbage: None,
+            time_up: None,
+            finished: None,
+            history: VecDeque::new(),
+            rewind_history: REWIND_HISTORY,
+            rewinds: 0,
+            fresh_world: true,
+        };
+        game.reseed(seed);
+        game
+    }
+
+    // Spawn a new piece for the given player, who controls it from now on
+    pub fn add_tetromino(&mut self, player: usize) {
+        // Taking the snapshot rebuilds the world, so a rewound game plays on like this one did
+        if self.rewind_history > 0 {
+            let snapshot = self.fork();
+            self.history.push_back(snapshot);
+            if self.history.len() > self.rewind_history {
+                self.history.pop_front();
+            }
+        }
+
+        // The player lets go of the piece they were controlling
+        if self.players[player].control_object.take().is_some() {
+            self.events.push_back(GameEvent::ControlLost);
+        }
+        let now = self.now();
+        self.players[player].last_spawn = Some(now);
+        self.spawned += 1;
+        self.scoring.piece_spawned();
+        // Every player spawns in their own part of the well
+        let x = LEFT + (self.well_right() - LEFT) * (2 * player + 1) as f32 / (2 * self.players.len()) as f32;
+        let rng = &mut self.rng;
+
+        let polyomino = &POLYOMINOS[Range::new(0, POLYOMINOS.len()).ind_sample(rng)];
+        // Initial rotation of this tetromino
+        let random_rotation = Range::new(0.0, 2.0 * ::std::f32::consts::PI).ind_sample(rng);
+        // Register the object at the top center
+        let mut tetr = Tetromino::new(&mut self.world, &polyomino[..], random_rotation,
+                                      Vector2::new(x, TOP - 3.0*BLOCK_DIST),
+                                      rand::Rand::rand(rng));
+        tetr.spawned = Some(now);
+        self.events.push_back(GameEvent::Spawned {
+            position: *tetr.rbh.borrow().center_of_mass(),
+            color: tetr.color,
+            blocks: tetr.cells.len(),
+        });
+        self.objects.push(tetr);
+        self.players[player].control_object = self.objects.last().cloned();
+    }
+
+    // Push a row of garbage with one random gap in from the bottom, shoving everything else up.
+    pub fn add_garbage_row(&mut self) {
+        self.last_garbage = Some(self.now());
+        self.events.push_back(GameEvent::GarbageAdded { rows: 1 });
+
+        for obj in self.objects.iter() {
+            obj.rbh.borrow_mut().append_translation(&Vector2::new(0.0, BLOCK_DIST));
+        }
+
+        let gap = Range::new(0, self.columns).ind_sample(&mut self.rng);
         let block_shape = ShapeHandle::new(shape::ConvexHull::new(block(BLOCK_SIZE/2.0, CORNER_RADIUS, EDGES_PER_CORNER)));
-        // let block_shape = ShapeHandle::new(shape::Cuboid::new(Vector2::new(BLOCK_SIZE/2.0, BLOCK_SIZE/2.0)));
-        // Description of all the blocks in a tetromino
-        let cuboids: Vec<_> = POLYOMINOS[Range::new(0, POLYOMINOS.len()).ind_sample(&mut rng)].iter()
-            // Each polyomino is described as a list of pairs of integers, describing the blocks in
-            // the plane that are part of the polyomino.
-            .map(|&[x, y]| {
-                let translation = BLOCK_DIST * Vector2::new(x as f32, y as f32);
-                let transform = Isometry2::new(translation, Vector1::new(0.0));
-                (transform, block_shape.clone())
+        // The blocks left and right of the gap each form a separate object
+        for columns in [0..gap, (gap + 1)..self.columns].iter().cloned().filter(|columns| columns.len() > 0) {
+            let cells: Vec<_> = columns.clone().map(|x| [x as i32, 0]).collect();
+            let blocks: Vec<_> = columns.map(|x| {
+                let translation = Vector2::new(LEFT + BLOCK_DIST * (x as f32 + 0.5), BOTTOM + BLOCK_DIST / 2.0);
+                (Isometry2::new(translation, Vector1::new(0.0)), block_shape.clone())
             }).collect();
-        // The tetromino shape
-        let total_shape = shape::Compound::new(cuboids);
+            let mut rb = RigidBody::new_dynamic(shape::Compound::new(blocks), GARBAGE_DENSITY, POLYOMINO_RESTITUTION, POLYOMINO_FRICTION);
+            rb.set_deactivation_threshold(None);
+            rb.set_margin(0.012);
+            self.objects.push(Tetromino {
+                rbh: self.world.add_rigid_body(rb),
+                color: GARBAGE_COLOR,
+                density: GARBAGE_DENSITY,
+                states: vec![BlockState::Normal; cells.len()],
+                cells: cells,
+                spawned: None,
+            });
+        }
+    }
 
-        let mut rb = RigidBody::new_dynamic(total_shape, POLYOMINO_DENSITY, POLYOMINO_RESTITUTION, POLYOMINO_FRICTION);
-        // Initial rotation of this tetromino
-        let random_rotation = Range::new(0.0, 2.0 * ::std::f32::consts::PI).ind_sample(&mut rng);
-        rb.append_rotation(&Vector1::new(random_rotation));
-        // Normalize position by moving the center of mass to the origin
-        let com = -rb.center_of_mass().to_vector();
-        rb.append_translation(&com);
-        // Move to top center
-        rb.append_translation(&Vector2::new((LEFT+RIGHT)/2.0, TOP - 3.0*BLOCK_DIST));
-        // Never deactivate a tetromino
-        rb.set_deactivation_threshold(None);
-        // Set a small margin
-        rb.set_margin(0.012);
+    // The object controlled by the first player
+    pub fn control_object(&self) -> Option<&Tetromino> {
+        self.player_object(0)
+    }
 
-        // Register the object
-        self.objects.push(Tetromino {
-            rbh: self.world.add_rigid_body(rb),
-            color: rand::Rand::rand(&mut rng),
-        });
-        self.control_object = self.objects.last().cloned();
+    pub fn player_object(&self, player: usize) -> Option<&Tetromino> {
+        self.players[player].control_object.as_ref()
+    }
+
+    // The controls currently applied to the object controlled by the first player
+    pub fn controls(&self) -> (Option<RotateMove>, Option<Move>) {
+        self.player_controls(0)
+    }
+
+    pub fn player_controls(&self, player: usize) -> (Option<RotateMove>, Option<Move>) {
+        (self.players[player].rotate, self.players[player].mov)
+    }
+
+    pub fn players(&self) -> usize { self.players.len() }
+    pub fn columns(&self) -> usize { self.columns }
+
+    // The x-coordinate of the right wall of the well
+    pub fn well_right(&self) -> f32 {
+        LEFT + self.columns as f32 * COLUMN_WIDTH
+    }
+
+    pub fn gravity(&self) -> Vector2<f32> {
+        self.levels.current().gravity()
+    }
+
+    // Where the object controlled by a player would come to rest if it dropped straight down without rotating
+    pub fn ghost_pose(&self, player: usize) -> Option<Isometry2<f32>> {
+        let obj = match self.players[player].control_object {
+            Some(ref obj) => obj,
+            None => return None,
+        };
+        let rb = obj.rbh.borrow();
+        let iso = *rb.position();
+        let down = Vector2::new(0.0, -1.0);
+        // Distance to the floor
+        let mut distance = obj.blocks().iter()
+            .flat_map(|block| block_outline(block).into_iter())
+            .map(|pt| (iso * pt).y - BOTTOM)
+            .fold(::std::f32::INFINITY, f32::min);
+        // Distance to every other object, including the pieces of other players
+        for other in self.objects.iter().filter(|other| &*other.rbh as *const _ != &*obj.rbh as *const _) {
+            let other_rb = other.rbh.borrow();
+            let toi = query::time_of_impact(&iso, &down, rb.shape().as_ref(),
+                                            other_rb.position(), &Vector2::new(0.0, 0.0), other_rb.shape().as_ref());
+            if let Some(toi) = toi {
+                distance = distance.min(toi);
+            }
+        }
+        let mut ghost = iso;
+        ghost.translation = ghost.translation + down * distance.max(0.0);
+        Some(ghost)
+    }
+
+    // True if any player controls the object
+    fn is_controlled(&self, tetr: &Tetromino) -> bool {
+        self.players.iter()
+            .filter_map(|player| player.control_object.as_ref())
+            .any(|obj| &*obj.rbh as *const _ == &*tetr.rbh as *const _)
+    }
+
+    // True if an object that's no longer controlled came to rest too high up the well.
+    // Pieces that were just spawned start out that high, so they get some time to fall first,
+    // e.g. when a line clear takes them out of the player's control right away.
+    pub fn topped_out(&self) -> bool {
+        let spawn_delay = Duration::from_millis(SPAWN_DELAY_MS);
+        self.objects.iter()
+            .filter(|tetr| !self.is_controlled(tetr))
+            .filter(|tetr| tetr.spawned.map_or(true, |time| self.since(time) >= spawn_delay))
+            .any(|tetr| {
+                let rb = tetr.rbh.borrow();
+                let &iso = rb.position();
+                let too_high = tetr.blocks().iter()
+                    .any(|&(inner_iso, _)| (iso.translation + iso.rotation.rotate(&inner_iso.translation)).y > TOP_OUT_HEIGHT);
+                too_high && rb.lin_vel().norm() < TOP_OUT_SPEED
+            })
     }
 
     pub fn tetrominos<'a>(&'a self) -> ::std::slice::Iter<'a, Tetromino> {
         self.objects.iter()
     }
 
+    // Execute an action for the first player
     pub fn execute_action(&mut self, action: Action) {
+        self.execute_player_action(0, action);
+    }
+
+    pub fn execute_player_action(&mut self, player: usize, action: Action) {
         match action {
-            Action::RotateCW   => self.rotate = Some(RotateMove::Clockwise),
-            Action::RotateCCW  => self.rotate = Some(RotateMove::Counterclockwise),
-            Action::RotateStop => self.rotate = None,
+            Action::GameReset => self.reset(),
+            Action::SelectMode(mode) => self.set_mode(mode),
+            Action::ToggleLineDetector => self.toggle_line_detector(),
+            Action::ToggleSplitMode => self.toggle_split_mode(),
+            // Pieces can't be controlled during the countdown, or after the game is over
+            _ if !self.accepting_input() => {},
 
-            Action::MoveLeft  => self.mov = Some(Move::Left),
-            Action::MoveRight => self.mov = Some(Move::Right),
-            Action::MoveStop  => self.mov = None,
+            Action::RotateCW   => self.players[player].rotate = Some(RotateMove::Clockwise),
+            Action::RotateCCW  => self.players[player].rotate = Some(RotateMove::Counterclockwise),
+            Action::RotateStop => self.players[player].rotate = None,
 
-            Action::TrySpawn  => { self.try_spawn(); },
-            Action::GameReset => self.reset(),
+            Action::MoveLeft  => self.players[player].mov = Some(Move::Left),
+            Action::MoveRight => self.players[player].mov = Some(Move::Right),
+            Action::MoveStop  => self.players[player].mov = None,
+
+            Action::TrySpawn  => { self.try_spawn(player); },
+            Action::Rewind    => { self.rewind(); },
         }
     }
-    pub fn try_spawn(&mut self) -> bool {
-        match self.last_spawn {
-            Some(instant) if instant.elapsed() < Duration::from_millis(SPAWN_DELAY_MS) => false,
+    pub fn try_spawn(&mut self, player: usize) -> bool {
+        match self.players[player].last_spawn {
+            Some(time) if self.since(time) < Duration::from_millis(SPAWN_DELAY_MS) => false,
             _ => {
-                self.add_tetromino();
+                self.add_tetromino(player);
                 true
             }
         }
     }
-    pub fn score(&self) -> usize { self.score }
+    pub fn score(&self) -> usize { self.scoring.score() }
+    pub fn recent_score_event(&self) -> Option<&ScoreEvent> { self.scoring.recent_event(self.now()) }
+    pub fn level(&self) -> usize { self.levels.number() }
+    pub fn lines(&self) -> usize { self.levels.lines() }
+    pub fn mode(&self) -> Mode { self.mode }
+    pub fn line_detector(&self) -> LineDetector { self.line_detector }
+
+    pub fn set_line_detector(&mut self, line_detector: LineDetector) {
+        self.line_detector = line_detector;
+        if let LineDetector::AreaCoverage(coverage) = line_detector {
+            self.line_coverage = coverage;
+        }
+    }
+
+    pub fn line_coverage(&self) -> f32 { self.line_coverage }
+
+    // Change the coverage of the area coverage detector, also if it's switched on right now
+    pub fn set_line_coverage(&mut self, coverage: f32) {
+        self.line_coverage = coverage;
+        if let LineDetector::AreaCoverage(_) = self.line_detector {
+            self.line_detector = LineDetector::AreaCoverage(coverage);
+        }
+    }
+
+    pub fn split_mode(&self) -> SplitMode { self.split_mode }
+
+    pub fn toggle_split_mode(&mut self) {
+        self.split_mode = match self.split_mode {
+            SplitMode::Remove => SplitMode::Cut,
+            SplitMode::Cut => SplitMode::Remove,
+        };
+    }
+
+    pub fn set_clear_duration(&mut self, duration: Duration) {
+        self.clear_duration = duration;
+    }
+
+    // How far along the current line clear animation is, from 0 to 1
+    pub fn clear_progress(&self) -> Option<f32> {
+        self.clearing.as_ref().map(|clearing| {
+            let elapsed = self.since(clearing.started);
+            let elapsed = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+            let duration = self.clear_duration.as_secs() as f32 + self.clear_duration.subsec_nanos() as f32 * 1e-9;
+            if duration > 0.0 { (elapsed / duration).min(1.0) } else { 1.0 }
+        })
+    }
+
+    pub fn toggle_line_detector(&mut self) {
+        self.line_detector = match self.line_detector {
+            LineDetector::BlockCenters => LineDetector::AreaCoverage(self.line_coverage),
+            LineDetector::AreaCoverage(_) => LineDetector::BlockCenters,
+        };
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.reset();
+    }
+
+    pub fn ticks(&self) -> u64 { self.ticks }
+    pub fn pieces_spawned(&self) -> usize { self.spawned }
+    pub fn seed(&self) -> u32 { self.seed }
+
+    // The game time: the time simulated since the game was reset
+    pub fn now(&self) -> Duration {
+        Duration::from_millis(self.ticks * TIMESTEP_MS)
+    }
+
+    // Game time passed since the given game time
+    fn since(&self, time: Duration) -> Duration {
+        let now = self.now();
+        if now > time { now - time } else { Duration::from_millis(0) }
+    }
+
+    // Time left before play starts
+    pub fn countdown(&self) -> Option<Duration> {
+        let now = self.now();
+        if now < self.started { Some(self.started - now) } else { None }
+    }
+
+    // Time spent playing, frozen once the game has finished or the time limit ran out
+    pub fn time_played(&self) -> Duration {
+        let end = self.time_up.or(self.finished.map(|(time, _)| time)).unwrap_or(self.now());
+        if end < self.started { Duration::from_millis(0) } else { end - self.started }
+    }
+
+    // Time until the next garbage row comes in
+    pub fn next_garbage(&self) -> Option<Duration> {
+        if self.mode != Mode::Survival || !self.accepting_input() { return None; }
+        let due = self.last_garbage.unwrap_or(self.started) + Duration::from_millis(GARBAGE_INTERVAL_MS);
+        let now = self.now();
+        Some(if now < due { due - now } else { Duration::from_millis(0) })
+    }
+
+    // Time left in modes with a time limit
+    pub fn time_left(&self) -> Option<Duration> {
+        self.mode.time_limit().map(|limit| {
+            let played = self.time_played();
+            if played < limit { limit - played } else { Duration::from_millis(0) }
+        })
+    }
+
+    // True after the time limit ran out, while waiting for the physics to settle
+    pub fn settling(&self) -> bool {
+        self.time_up.is_some() && self.finished.is_none()
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        self.finished.map(|(_, outcome)| outcome)
+    }
+
+    pub fn accepting_input(&self) -> bool {
+        self.finished.is_none() && self.time_up.is_none() && self.countdown().is_none()
+    }
+
+    fn finish(&mut self, outcome: Outcome) {
+        self.finished = Some((self.now(), outcome));
+        self.lose_control();
+        self.events.push_back(GameEvent::GameOver { outcome: outcome });
+    }
+
+    // Every player loses control of their object
+    fn lose_control(&mut self) {
+        for player in self.players.iter_mut() {
+            if player.control_object.take().is_some() {
+                self.events.push_back(GameEvent::ControlLost);
+            }
+        }
+    }
+
+    // Events that happened since the last call
+    pub fn drain_events(&mut self) -> ::std::collections::vec_deque::Drain<GameEvent> {
+        self.events.drain(..)
+    }
 
     pub fn update(&mut self) {
-        // If there's an object controlled by the player, move it
-        if let Some(ref obj) = self.control_object {
-            let ref rbh = obj.rbh;
-            match self.rotate {
-                Some(RotateMove::Clockwise) => rbh.borrow_mut().apply_angular_momentum(Vector1::new(POLYOMINO_ANG_FORCE)),
-                Some(RotateMove::Counterclockwise) => rbh.borrow_mut().apply_angular_momentum(Vector1::new(-POLYOMINO_ANG_FORCE)),
-                None => {}
+        // Stop the game once the time limit runs out, and wait for everything to come to rest
+        if self.time_up.is_none() && self.time_left() == Some(Duration::from_millis(0)) {
+            self.time_up = Some(self.now());
+            self.lose_control();
+        }
+        if let Some(time_up) = self.time_up {
+            let settled = self.objects.iter().all(|tetr| {
+                let rb = tetr.rbh.borrow();
+                rb.lin_vel().norm() < SETTLE_SPEED && rb.ang_vel().x.abs() < SETTLE_SPEED
+            });
+            if self.finished.is_none() && (settled || self.since(time_up) >= Duration::from_millis(SETTLE_TIMEOUT_MS)) {
+                let score = self.score();
+                self.finish(Outcome::UltraScore(score));
             }
-            match self.mov {
-                Some(Move::Left)  => rbh.borrow_mut().apply_central_impulse(Vector2::new(-POLYOMINO_FORCE, 0.0)),
-                Some(Move::Right) => rbh.borrow_mut().apply_central_impulse(Vector2::new(POLYOMINO_FORCE, 0.0)),
-                None => {},
+        }
+
+        if self.mode == Mode::Survival && self.accepting_input() && self.clearing.is_none() {
+            // Garbage rows keep coming in
+            let interval = Duration::from_millis(GARBAGE_INTERVAL_MS);
+            if self.since(self.last_garbage.unwrap_or(self.started)) >= interval {
+                self.add_garbage_row();
+            }
+        }
+        if self.mode.tops_out() && self.accepting_input() && self.clearing.is_none() && self.topped_out() {
+            let time = self.time_played();
+            self.finish(match self.mode {
+                Mode::Survival => Outcome::SurvivalTime(time),
+                _ => Outcome::ToppedOut(time),
+            });
+        }
+
+        // Force the next piece in if a player takes too long
+        let auto_spawn = Duration::from_millis(self.levels.current().auto_spawn_ms);
+        for player in 0..self.players.len() {
+            let last_spawn = self.players[player].last_spawn.unwrap_or(self.started);
+            if self.accepting_input() && self.since(last_spawn) >= auto_spawn {
+                self.add_tetromino(player);
             }
         }
-        else {
-            // No object is controlled by the player, unconditionally reset movement
-            self.rotate = None;
-            self.mov = None;
+
+        for player in self.players.iter_mut() {
+            // If there's an object controlled by the player, move it
+            if let Some(ref obj) = player.control_object {
+                apply_controls(&mut obj.rbh.borrow_mut(), player.rotate, player.mov);
+            }
+            else {
+                // No object is controlled by the player, unconditionally reset movement
+                player.rotate = None;
+                player.mov = None;
+            }
         }
 
         // Removing completed lines
+        match self.clear_progress() {
+            Some(progress) if progress >= 1.0 => self.finish_clear(),
+            Some(_) => {},
+            None => {
+                let line_heights = self.line_detector.find_lines(&self.objects, self.columns);
+                if !line_heights.is_empty() {
+                    self.start_clear(line_heights);
+                }
+            },
+        }
+        // A clear that doesn't take any time is finished immediately
+        if self.clear_duration == Duration::from_millis(0) {
+            self.finish_clear();
+        }
 
-        // Collect the y-coordinate of all individual blocks, and sort them.
-        let mut block_heights: Vec<f32> = self.objects.iter()
-            .flat_map(|tetr| {
-                let orig_iso = *tetr.rbh.borrow().position();
-                tetr.rbh.borrow().shape()
-                    .as_shape::<shape::Compound<_, _>>().unwrap()
-                    .shapes().iter()
-                    .map(|&(iso, _)| (orig_iso.translation + orig_iso.rotation.rotate(&iso.translation)).y)
-                    .collect::<Vec<_>>().into_iter()
+        self.detect_impacts();
+
+        // Update the physics world
+        self.world.step(TIMESTEP);
+        self.fresh_world = false;
+        self.ticks += 1;
+    }
+
+    // Look for contacts between objects that are hitting each other hard
+    fn detect_impacts(&mut self) {
+        let mut constraints = vec![];
+        self.world.constraints(&mut constraints);
+        self.contacts.clear();
+        for constraint in constraints {
+            if let Constraint::RBRB(rb1, rb2, contact) = constraint {
+                let (rb1, rb2) = (rb1.borrow(), rb2.borrow());
+                let position = Point2::new((contact.world1.x + contact.world2.x) / 2.0,
+                                           (contact.world1.y + contact.world2.y) / 2.0);
+                self.contacts.push((position, contact.normal));
+                // Velocity of each body at the contact point
+                let velocity_at = |rb: &RigidBody<f32>| {
+                    let offset = position - *rb.center_of_mass();
+                    rb.lin_vel() + Vector2::new(-rb.ang_vel().x * offset.y, rb.ang_vel().x * offset.x)
+                };
+                let approach_speed = ::na::dot(&(velocity_at(&*rb1) - velocity_at(&*rb2)), &contact.normal);
+                let inv_mass = rb1.inv_mass() + rb2.inv_mass();
+                if approach_speed <= 0.0 || inv_mass == 0.0 { continue; }
+                // The impulse that would stop the approach if the bodies didn't rotate. The solver's
+                // own impulses aren't available, so this only estimates how hard the hit is.
+                let estimated_impulse = approach_speed / inv_mass;
+                if estimated_impulse >= IMPACT_ESTIMATED_IMPULSE {
+                    self.events.push_back(GameEvent::Impact {
+                        position: position,
+                        normal: contact.normal,
+                        estimated_impulse: estimated_impulse,
+                    });
+                }
+            }
+        }
+    }
+
+    // Contact points and normals between all objects, as of the last update
+    pub fn contacts(&self) -> &[(Point2<f32>, Vector2<f32>)] {
+        &self.contacts
+    }
+
+    fn start_clear(&mut self, line_heights: Vec<(f32, f32)>) {
+        // At least one line was found.
+        let score_event = self.scoring.lines_cleared(line_heights.len(), self.columns, self.now());
+        self.events.push_back(GameEvent::ScoreChanged { score: self.scoring.score(), event: score_event });
+        if self.levels.lines_cleared(line_heights.len()) {
+            self.world.set_gravity(self.levels.current().gravity());
+            self.events.push_back(GameEvent::LevelChanged { level: self.levels.number() });
+        }
+
+        // When cutting, always remove a band as high as a full line
+        let split_mode = self.split_mode;
+        let lines: Vec<_> = line_heights.into_iter()
+            .map(|(y_pos, threshold)| match split_mode {
+                SplitMode::Remove => (y_pos, threshold),
+                SplitMode::Cut => (y_pos, threshold.max(BLOCK_DIST / 2.0)),
             }).collect();
-        block_heights.sort_by(|&y1, &y2| y1.partial_cmp(&y2).unwrap());
-        // All groups of BLOCKS_PER_LINE blocks that are at approximately the same height
-        // form a line. Each element of `line_heights` represent a horizontal line
-        // that will be deleted.
-        let line_heights: Vec<(_, _)> = block_heights.iter().cloned()
-            .zip(block_heights.iter().cloned().skip(BLOCKS_PER_LINE - 1))
-            .filter(|&(y1, y2)| y2 - y1 < LINE_THRESHOLD)
-            .fold((vec![], 0.0), |(mut lines, min_y), (y1, y2)| {
-                if y1 <= min_y {
-                    // This completed line overlaps with another completed line
-                    // This means there's more than BLOCKS_PER_LINE blocks located
-                    // on the same horizontal line - this should not happen for
-                    // reasonable values of BLOCKS_PER_LINE!
-                    return (lines, min_y);
+
+        // Mark the affected blocks, and freeze the objects they're part of. A line takes control
+        // away from the player, but in co-op only from the players controlling one of those.
+        let coop = self.players.len() > 1;
+        if !coop {
+            self.lose_control();
+        }
+        let mut frozen = vec![];
+        let mut blocks_removed = 0;
+        for tetr in self.objects.iter_mut() {
+            let mut affected = false;
+            for &(y_pos, threshold) in lines.iter() {
+                let in_band = tetr.blocks_in_band(y_pos, threshold, split_mode);
+                for (state, in_band) in tetr.states.iter_mut().zip(in_band) {
+                    if in_band && *state != BlockState::Clearing {
+                        *state = BlockState::Clearing;
+                        blocks_removed += 1;
+                        affected = true;
+                    }
+                }
+            }
+            if affected && coop {
+                for player in self.players.iter_mut() {
+                    let controlled = player.control_object.as_ref()
+                        .map_or(false, |obj| &*obj.rbh as *const _ == &*tetr.rbh as *const _);
+                    if controlled {
+                        player.control_object = None;
+                        self.events.push_back(GameEvent::ControlLost);
+                    }
                 }
-                let center = (y2 + y1) / 2.0;
-                let threshold = (y2 - y1) / 2.0;
-                lines.push((center, threshold));
-                (lines, y2)
-            }).0;
-
-        if !line_heights.is_empty() {
-            // At least one line was found.
-            self.score += 10 * line_heights.len();
-            self.control_object = None;
-
-            let &mut Game { ref mut objects, ref mut world, .. } = self;
-
-            for (y_pos, threshold) in line_heights {
-                let mut new_objects = vec![];
-                objects.retain(|tetr| {
-                    if tetr.requires_split(y_pos, threshold) {
+            }
+            if affected {
+                self.world.remove_rigid_body(&tetr.rbh);
+                tetr.rbh = self.world.add_rigid_body(tetr.clone_static_body());
+                frozen.push(tetr.rbh.clone());
+            }
+        }
+        self.events.push_back(GameEvent::LinesCleared {
+            heights: lines.iter().map(|&(y_pos, _)| y_pos).collect(),
+            blocks_removed: blocks_removed,
+        });
+        self.clearing = Some(Clearing {
+            started: self.now(),
+            lines: lines,
+            frozen: frozen,
+        });
+
+        if self.mode == Mode::Sprint && self.finished.is_none() && self.levels.lines() >= SPRINT_LINES {
+            let time = self.time_played();
+            self.finish(Outcome::SprintTime(time));
+        }
+    }
+
+    fn finish_clear(&mut self) {
+        let clearing = match self.clearing.take() {
+            Some(clearing) => clearing,
+            None => return,
+        };
+        let split_mode = self.split_mode;
+        let world = &mut self.world;
+
+        // Only the objects that were frozen at the start of the clear are affected
+        let (mut affected, others): (Vec<_>, Vec<_>) = self.objects.drain(..)
+            .partition(|tetr| clearing.frozen.iter().any(|rbh| &**rbh as *const _ == &*tetr.rbh as *const _));
+
+        for tetr in affected.iter() {
+            let iso = *tetr.rbh.borrow().position();
+            for (&(inner_iso, _), &state) in tetr.blocks().iter().zip(tetr.states.iter()) {
+                if state == BlockState::Clearing {
+                    self.events.push_back(GameEvent::BlockRemoved {
+                        position: iso * inner_iso.translation.to_point(),
+                        color: tetr.color,
+                    });
+                }
+            }
+        }
+
+        let mut splits = vec![];
+        for &(y_pos, threshold) in clearing.lines.iter() {
+            affected = affected.into_iter()
+                .flat_map(|tetr| {
+                    if tetr.requires_split(y_pos, threshold, split_mode) {
                         // This object is affected: remove it from the physics world...
                         world.remove_rigid_body(&tetr.rbh);
                         // and compute the resulting new objects + add them back in.
-                        new_objects.extend_from_slice(&tetr.clone().split_blocks(world, y_pos, threshold));
-                        false
+                        let fragments = tetr.clone().split_blocks(world, y_pos, threshold, split_mode);
+                        if cfg!(feature="split-checks") {
+                            if let Err(message) = verify::check_split(&tetr, &fragments, y_pos, threshold, split_mode) {
+                                panic!("Invalid split: {}", message);
+                            }
+                        }
+                        splits.push(fragments.len());
+                        fragments
                     }
                     // This object is not affected.
-                    else { true }
-                });
-                // Add all newly generated objects back into the list of objects.
-                objects.extend_from_slice(&new_objects)
-            }
+                    else { vec![tetr] }
+                }).collect();
         }
 
-        // Update the physics world
-        self.world.step(0.016);
+        // Frozen objects that weren't split after all start moving again. Fragments already do.
+        let affected: Vec<_> = affected.into_iter()
+            .map(|tetr| {
+                if clearing.frozen.iter().any(|rbh| &**rbh as *const _ == &*tetr.rbh as *const _) {
+                    world.remove_rigid_body(&tetr.rbh);
+                    Tetromino { rbh: world.add_rigid_body(tetr.clone_body()), ..tetr }
+                } else {
+                    tetr
+                }
+            }).collect();
+
+        self.events.extend(splits.into_iter().map(|fragments| GameEvent::Split { fragments: fragments }));
+
+        // Add all newly generated objects back into the list of objects.
+        self.objects = others;
+        for mut tetr in affected {
+            tetr.states = vec![BlockState::Normal; tetr.states.len()];
+            self.objects.push(tetr);
+        }
     }
 
-    pub fn reset(&mut self) {
-        for obj in self.objects.drain(..) {
-            self.world.remove_rigid_body(&obj.rbh);
+    // An independent copy of this game, that evolves identically under the same inputs.
+    // The physics world can't be copied directly, and the contact and solver caches in it influence
+    // how it evolves, so this game's world is rebuilt from scratch as well. Forking is deterministic,
+    // but it does change how this game plays out, unless the world is fresh (see `has_fresh_world`).
+    pub fn fork(&mut self) -> Game {
+        self.rebuild_world();
+        self.preview()
+    }
+
+    // Like `fork`, but the copy can also be rewound like this game
+    pub fn fork_with_history(&mut self) -> Game {
+        let mut fork = self.fork();
+        fork.history = self.history.iter_mut().map(Game::fork).collect();
+        fork
+    }
+
+    // A copy of this game to look ahead with, which leaves this game untouched. The copy gets a new
+    // world without the caches of this one, so it only plays out approximately like this game,
+    // unless this game's world is fresh.
+    pub fn preview(&self) -> Game {
+        let (world, objects, players, clearing) = self.copy_world();
+        Game {
+            world: world,
+            objects: objects,
+            players: players,
+            columns: self.columns,
+            scoring: self.scoring.clone(),
+            levels: self.levels.clone(),
+            line_detector: self.line_detector,
+            line_coverage: self.line_coverage,
+            split_mode: self.split_mode,
+            clearing: clearing,
+            clear_duration: self.clear_duration,
+            events: VecDeque::new(),
+            contacts: self.contacts.clone(),
+            mode: self.mode,
+            ticks: self.ticks,
+            spawned: self.spawned,
+            seed: self.seed,
+            rng: self.rng.clone(),
+            started: self.started,
+            last_garbage: self.last_garbage,
+            time_up: self.time_up,
+            finished: self.finished,
+            history: VecDeque::new(),
+            rewind_history: self.rewind_history,
+            rewinds: self.rewinds,
+            fresh_world: true,
         }
-        self.control_object = None;
-        self.rotate = None;
-        self.mov = None;
-        self.score = 0;
-        self.last_spawn = None;
-        self.last_score = None;
+    }
+
+    // True if the physics world wasn't stepped since it was built from scratch: after a reset, a
+    // rewind, or a spawn that took a snapshot for rewinding. Objects added since then are added
+    // just like a rebuild would, so forking the game now leaves it as it is.
+    pub fn has_fresh_world(&self) -> bool { self.fresh_world }
+
+    fn rebuild_world(&mut self) {
+        let (world, objects, players, clearing) = self.copy_world();
+        self.world = world;
+        self.objects = objects;
+        self.players = players;
+        self.clearing = clearing;
+        self.fresh_world = true;
+    }
+
+    // A new world with a copy of every object, in the same order and with the same pose and
+    // velocity, together with the copies of the objects and the state that refers to them.
+    fn copy_world(&self) -> (World<f32>, Vec<Tetromino>, Vec<Player>, Option<Clearing>) {
+        let mut world = create_world(self.well_right());
+        world.set_gravity(self.gravity());
+        let frozen = |tetr: &Tetromino| self.clearing.as_ref().map_or(false, |clearing| {
+            clearing.frozen.iter().any(|rbh| &**rbh as *const _ == &*tetr.rbh as *const _)
+        });
+        let objects: Vec<_> = self.objects.iter()
+            .map(|tetr| {
+                let body = if frozen(tetr) { tetr.clone_static_body() } else { tetr.clone_body() };
+                Tetromino { rbh: world.add_rigid_body(body), ..tetr.clone() }
+            }).collect();
+
+        let index_of = |rbh: &RigidBodyHandle<f32>| {
+            self.objects.iter().position(|tetr| &*tetr.rbh as *const _ == &**rbh as *const _)
+        };
+        let players = self.players.iter()
+            .map(|player| Player {
+                control_object: player.control_object.as_ref()
+                    .and_then(|obj| index_of(&obj.rbh))
+                    .map(|i| objects[i].clone()),
+                ..player.clone()
+            }).collect();
+        let clearing = self.clearing.as_ref().map(|clearing| Clearing {
+            frozen: clearing.frozen.iter()
+                .filter_map(|rbh| index_of(rbh).map(|i| objects[i].rbh.clone()))
+                .collect(),
+            ..clearing.clone()
+        });
+        (world, objects, players, clearing)
+    }
+
+    // Restore the game to just before the last piece was spawned. The settings and the controls
+    // being held are kept. Returns false if there's nothing to rewind to.
+    pub fn rewind(&mut self) -> bool {
+        let mut snapshot = match self.history.pop_back() {
+            Some(snapshot) => snapshot,
+            None => return false,
+        };
+        snapshot.history = mem::replace(&mut self.history, VecDeque::new());
+        snapshot.rewinds = self.rewinds + 1;
+        snapshot.events = mem::replace(&mut self.events, VecDeque::new());
+        for (player, current) in snapshot.players.iter_mut().zip(self.players.iter()) {
+            player.rotate = current.rotate;
+            player.mov = current.mov;
+        }
+        snapshot.line_detector = self.line_detector;
+        snapshot.line_coverage = self.line_coverage;
+        snapshot.split_mode = self.split_mode;
+        snapshot.clear_duration = self.clear_duration;
+        snapshot.rewind_history = self.rewind_history;
+        *self = snapshot;
+        self.events.push_back(GameEvent::Rewound { rewinds: self.rewinds });
+        true
+    }
+
+    // Number of times the game was rewound
+    pub fn rewinds(&self) -> usize { self.rewinds }
+
+    // Change the number of spawns that can be undone. Games that are never rewound, like the
+    // simulations of the bot, skip taking snapshots with 0.
+    pub fn set_rewind_history(&mut self, spawns: usize) {
+        self.rewind_history = spawns;
+        while self.history.len() > spawns {
+            self.history.pop_front();
+        }
+    }
+
+    // Restart with the same settings, but a new random seed
+    pub fn reset(&mut self) {
+        let seed = rand::thread_rng().gen();
+        self.reseed(seed);
+    }
+
+    // Restart with the same settings, and the given seed
+    pub fn reseed(&mut self, seed: u32) {
+        // Start from a fresh world, so that nothing is left over from the previous game
+        self.columns = self.mode.columns();
+        self.world = create_world(self.well_right());
+        self.fresh_world = true;
+        self.objects.clear();
+        self.players = vec![Player::new(); self.mode.players()];
+        self.clearing = None;
+        self.events.clear();
+        self.scoring.reset();
+        self.levels.reset();
+        self.world.set_gravity(self.levels.current().gravity());
+        self.ticks = 0;
+        self.spawned = 0;
+        self.seed = seed;
+        self.rng = seeded_rng(seed);
+        self.started = self.mode.countdown();
+        self.last_garbage = None;
+        self.time_up = None;
+        self.finished = None;
+        self.history.clear();
+        self.rewinds = 0;
     }
 }
 
-// Create and setup a new world with boundaries
-fn create_world() -> World<f32> {
+// Push or rotate a controlled object
+pub fn apply_controls(rb: &mut RigidBody<f32>, rotate: Option<RotateMove>, mov: Option<Move>) {
+    match rotate {
+        Some(RotateMove::Clockwise) => rb.apply_angular_momentum(Vector1::new(POLYOMINO_ANG_FORCE)),
+        Some(RotateMove::Counterclockwise) => rb.apply_angular_momentum(Vector1::new(-POLYOMINO_ANG_FORCE)),
+        None => {}
+    }
+    match mov {
+        Some(Move::Left)  => rb.apply_central_impulse(Vector2::new(-POLYOMINO_FORCE, 0.0)),
+        Some(Move::Right) => rb.apply_central_impulse(Vector2::new(POLYOMINO_FORCE, 0.0)),
+        None => {},
+    }
+}
+
+// The random number generator for a given seed. It's seeded with a mix of the seed and some
+// constants, since it must not be seeded with all zeroes.
+fn seeded_rng(seed: u32) -> XorShiftRng {
+    XorShiftRng::from_seed([seed, seed ^ 0x9e3779b9, 0x243f6a88, 0xb7e15162])
+}
+
+// Create and setup a new world with boundaries, with the right wall at the given x-coordinate
+pub fn create_world(right: f32) -> World<f32> {
     let mut world = World::new();
     world.set_gravity(GRAVITY);
     let mut plane_geom = RigidBody::new_static(shape::Plane::new(-GRAVITY), WALL_RESTITUTION, WALL_FRICTION);
@@ -306,11 +1134,83 @@ fn create_world() -> World<f32> {
     plane_geom.append_translation(&Vector2::new(LEFT, 0.0));
     world.add_rigid_body(plane_geom);
     let mut plane_geom = RigidBody::new_static(shape::Plane::new(Vector2::new(-1.0, 0.0)), WALL_RESTITUTION, WALL_FRICTION);
-    plane_geom.append_translation(&Vector2::new(RIGHT, 0.0));
+    plane_geom.append_translation(&Vector2::new(right, 0.0));
     world.add_rigid_body(plane_geom);
     world
 }
 
+// Divide `count` blocks into groups of connected blocks, given which pairs of blocks are connected.
+// Returns the indices of the blocks in each group.
+pub fn connected_components<F: Fn(usize, usize) -> bool>(count: usize, connected: F) -> Vec<Vec<usize>> {
+    let mut visited = vec![false; count];
+    let mut components = vec![];
+    for start in 0..count {
+        if visited[start] { continue; }
+        visited[start] = true;
+        // Breadth-first search from this block
+        let mut component = vec![start];
+        let mut i = 0;
+        while i < component.len() {
+            let current = component[i];
+            for other in 0..count {
+                if !visited[other] && connected(current, other) {
+                    visited[other] = true;
+                    component.push(other);
+                }
+            }
+            i += 1;
+        }
+        components.push(component);
+    }
+    // Every block ends up in exactly one component
+    debug_assert!({
+        let mut all: Vec<usize> = components.iter().flat_map(|c| c.iter().cloned()).collect();
+        all.sort();
+        all == (0..count).collect::<Vec<_>>()
+    });
+    components
+}
+
+// True for cells next to each other in the grid of a polyomino
+pub fn cells_adjacent(a: [i32; 2], b: [i32; 2]) -> bool {
+    (a[0] - b[0]).abs() + (a[1] - b[1]).abs() == 1
+}
+
+// Two blocks of an object stay connected if their cells are next to each other, and they still
+// touch. Fragments of cut blocks keep the cell of their block, but may have lost the side that
+// faced the neighbouring block.
+pub fn blocks_connected(a: &Block, cell_a: [i32; 2], b: &Block, cell_b: [i32; 2]) -> bool {
+    cells_adjacent(cell_a, cell_b) &&
+        outline_distance(&block_outline(a), &block_outline(b)) <= CONNECTED_DISTANCE
+}
+
+// The distance between two convex outlines that don't overlap
+fn outline_distance(a: &[Point2<f32>], b: &[Point2<f32>]) -> f32 {
+    let to_edges = |pt: &Point2<f32>, poly: &[Point2<f32>]| {
+        (0..poly.len())
+            .map(|i| segment_distance(pt, &poly[i], &poly[(i + 1) % poly.len()]))
+            .fold(::std::f32::INFINITY, f32::min)
+    };
+    let a_to_b = a.iter().map(|pt| to_edges(pt, b)).fold(::std::f32::INFINITY, f32::min);
+    let b_to_a = b.iter().map(|pt| to_edges(pt, a)).fold(::std::f32::INFINITY, f32::min);
+    a_to_b.min(b_to_a)
+}
+
+fn segment_distance(pt: &Point2<f32>, p1: &Point2<f32>, p2: &Point2<f32>) -> f32 {
+    let edge = *p2 - *p1;
+    let length2 = edge.norm_squared();
+    let t = if length2 > 0.0 { (::na::dot(&(*pt - *p1), &edge) / length2).max(0.0).min(1.0) } else { 0.0 };
+    (*pt - (*p1 + edge * t)).norm()
+}
+
+// The outline of a block (or a fragment of a block) in the frame of the object it's part of.
+pub fn block_outline(&(iso, ref shape): &Block) -> Vec<Point2<f32>> {
+    shape.as_shape::<shape::ConvexHull<Point2<f32>>>().unwrap()
+        .points().iter()
+        .map(|&pt| iso * pt)
+        .collect()
+}
+
 // Create a list of points describing the convex hull of one block.
 pub fn block(half_size: f32, radius: f32, points: u32) -> Vec<Point2<f32>> {
     let l1 = half_size - radius;
@@ -330,3 +1230,243 @@ pub fn block(half_size: f32, radius: f32, points: u32) -> Vec<Point2<f32>> {
     }
     result
 }
+
+#[cfg(test)]
+pub mod tests {
+    use na::{Point2, Vector1, Vector2};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use rand::distributions::{IndependentSample, Range};
+
+    use consts::*;
+    use controls::Action;
+    use events::GameEvent;
+    use modes::Mode;
+    use netcode::pose_checksum;
+    use super::{block_outline, cells_adjacent, connected_components, create_world, outline_distance, Game, SplitMode, Tetromino};
+
+    const SAMPLES: usize = 100;
+
+    const ACTIONS: [Action; 7] = [
+        Action::RotateCW, Action::RotateCCW, Action::RotateStop,
+        Action::MoveLeft, Action::MoveRight, Action::MoveStop,
+        Action::TrySpawn,
+    ];
+
+    pub fn test_rng() -> XorShiftRng {
+        XorShiftRng::from_seed([0x193a6754, 0xa8a7d469, 0x97830e05, 0x113ba7bb])
+    }
+
+    // Split a polyomino at a random rotation and velocity along a random line across it. Returns the
+    // parent, the fragments and the line (center and half height), or None if the line missed.
+    pub fn random_split<R: Rng>(rng: &mut R, polyomino: &[[usize; 2]], mode: SplitMode) -> Option<(Tetromino, Vec<Tetromino>, f32, f32)> {
+        let mut world = create_world(RIGHT);
+        let rotation = Range::new(0.0, 2.0 * ::std::f32::consts::PI).ind_sample(rng);
+        let center = Vector2::new((LEFT + RIGHT) / 2.0, (BOTTOM + TOP) / 2.0);
+        let parent = Tetromino::new(&mut world, polyomino, rotation, center, [1.0, 1.0, 1.0]);
+        {
+            let mut rb = parent.rbh.borrow_mut();
+            let velocity = Range::new(-5.0, 5.0);
+            rb.set_lin_vel(Vector2::new(velocity.ind_sample(rng), velocity.ind_sample(rng)));
+            rb.set_ang_vel(Vector1::new(velocity.ind_sample(rng)));
+        }
+        let extent = BLOCK_DIST * polyomino.len() as f32 / 2.0;
+        let y_pos = center.y + Range::new(-extent, extent).ind_sample(rng);
+        let threshold = match mode {
+            SplitMode::Remove => Range::new(0.0, LINE_THRESHOLD / 2.0).ind_sample(rng),
+            SplitMode::Cut => BLOCK_DIST / 2.0,
+        };
+        if !parent.requires_split(y_pos, threshold, mode) {
+            return None;
+        }
+        world.remove_rigid_body(&parent.rbh);
+        let fragments = parent.clone().split_blocks(&mut world, y_pos, threshold, mode);
+        Some((parent, fragments, y_pos, threshold))
+    }
+
+    // True if the given items are all connected to each other, found without `connected_components`
+    fn is_connected<F: Fn(usize, usize) -> bool>(count: usize, connected: F) -> bool {
+        let mut reached = vec![false; count];
+        if count > 0 { reached[0] = true; }
+        loop {
+            let next = (0..count).find(|&i| !reached[i] && (0..count).any(|j| reached[j] && connected(i, j)));
+            match next {
+                Some(i) => reached[i] = true,
+                None => return reached.iter().all(|&reached| reached),
+            }
+        }
+    }
+
+    fn assert_partition(components: &[Vec<usize>], count: usize) {
+        let mut all: Vec<usize> = components.iter().flat_map(|component| component.iter().cloned()).collect();
+        all.sort();
+        assert_eq!(all, (0..count).collect::<Vec<_>>(), "blocks lost or duplicated in {:?}", components);
+    }
+
+    #[test]
+    fn components_of_random_cells() {
+        let mut rng = test_rng();
+        for _ in 0..10 * SAMPLES {
+            // Up to a dozen cells in a small grid, so that some are connected and some aren't
+            let mut cells: Vec<[i32; 2]> = vec![];
+            for _ in 0..rng.gen_range(0, 13) {
+                let cell = [rng.gen_range(0, 5), rng.gen_range(0, 5)];
+                if !cells.contains(&cell) {
+                    cells.push(cell);
+                }
+            }
+            let components = connected_components(cells.len(), |i, j| cells_adjacent(cells[i], cells[j]));
+            assert_partition(&components, cells.len());
+            for (n, component) in components.iter().enumerate() {
+                assert!(is_connected(component.len(), |i, j| cells_adjacent(cells[component[i]], cells[component[j]])),
+                        "component {:?} of {:?} isn't connected", component, cells);
+                for other in components[n + 1..].iter() {
+                    assert!(!component.iter().any(|&i| other.iter().any(|&j| cells_adjacent(cells[i], cells[j]))),
+                            "components {:?} and {:?} of {:?} are connected", component, other, cells);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn split_keeps_every_block_once() {
+        let mut rng = test_rng();
+        for polyomino in POLYOMINOS.iter() {
+            for _ in 0..SAMPLES {
+                for &mode in [SplitMode::Remove, SplitMode::Cut].iter() {
+                    let (parent, fragments, y_pos, threshold) = match random_split(&mut rng, &polyomino[..], mode) {
+                        Some(split) => split,
+                        None => continue,
+                    };
+                    let in_band = parent.blocks_in_band(y_pos, threshold, mode);
+                    for (&cell, &in_band) in parent.cells.iter().zip(in_band.iter()) {
+                        let copies = fragments.iter().flat_map(|frag| frag.cells.iter()).filter(|&&other| other == cell).count();
+                        // Blocks outside the line are kept as they are, and cut blocks leave at most a
+                        // fragment on either side of it
+                        let expected = match (mode, in_band) {
+                            (_, false) => copies == 1,
+                            (SplitMode::Remove, true) => copies == 0,
+                            (SplitMode::Cut, true) => copies <= 2,
+                        };
+                        assert!(expected, "{:?} split of {:?} has {} copies of block {:?}", mode, polyomino, copies, cell);
+                    }
+                    for frag in fragments.iter() {
+                        assert_eq!(frag.cells.len(), frag.blocks().len());
+                        assert!(frag.cells.iter().all(|cell| parent.cells.contains(cell)));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn split_fragments_are_connected_and_apart() {
+        let mut rng = test_rng();
+        for polyomino in POLYOMINOS.iter() {
+            for _ in 0..SAMPLES {
+                for &mode in [SplitMode::Remove, SplitMode::Cut].iter() {
+                    let fragments = match random_split(&mut rng, &polyomino[..], mode) {
+                        Some((_, fragments, _, _)) => fragments,
+                        None => continue,
+                    };
+                    // The outlines of the blocks in the world, with their cells
+                    let blocks: Vec<Vec<(Vec<Point2<f32>>, [i32; 2])>> = fragments.iter()
+                        .map(|frag| {
+                            let iso = *frag.rbh.borrow().position();
+                            frag.blocks().iter().zip(frag.cells.iter())
+                                .map(|(block, &cell)| (block_outline(block).into_iter().map(|pt| iso * pt).collect(), cell))
+                                .collect()
+                        }).collect();
+                    let touch = |&(ref a, cell_a): &(Vec<Point2<f32>>, [i32; 2]), &(ref b, cell_b): &(Vec<Point2<f32>>, [i32; 2])| {
+                        cells_adjacent(cell_a, cell_b) && outline_distance(a, b) <= CONNECTED_DISTANCE
+                    };
+                    for (n, frag) in blocks.iter().enumerate() {
+                        assert!(is_connected(frag.len(), |i, j| touch(&frag[i], &frag[j])),
+                                "{:?} split of {:?} has a fragment that falls apart: {:?}",
+                                mode, polyomino, frag.iter().map(|&(_, cell)| cell).collect::<Vec<_>>());
+                        for other in blocks[n + 1..].iter() {
+                            assert!(!frag.iter().any(|a| other.iter().any(|b| touch(a, b))),
+                                    "{:?} split of {:?} has fragments that still touch", mode, polyomino);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn forks_evolve_like_the_game() {
+        let mut rng = test_rng();
+        let mut game = Game::with_seed(21);
+        for _ in 0..437 {
+            if rng.gen_weighted_bool(10) {
+                game.execute_action(*rng.choose(&ACTIONS).unwrap());
+            }
+            game.update();
+        }
+        assert!(game.tetrominos().len() > 1 && !game.has_fresh_world());
+
+        let mut fork = game.fork();
+        for tick in 0..300 {
+            if rng.gen_weighted_bool(10) {
+                let action = *rng.choose(&ACTIONS).unwrap();
+                game.execute_action(action);
+                fork.execute_action(action);
+            }
+            game.update();
+            fork.update();
+            assert_eq!(pose_checksum(&[&game]), pose_checksum(&[&fork]), "the fork diverged at tick {}", tick);
+        }
+    }
+
+    // Replays take their keyframes whenever the world is fresh, which mustn't change the game
+    #[test]
+    fn forking_a_fresh_world_leaves_the_game_alone() {
+        let mut rng = test_rng();
+        let (mut forked, mut untouched) = (Game::with_seed(22), Game::with_seed(22));
+        let mut forks = 0;
+        for tick in 0..1200 {
+            if rng.gen_weighted_bool(10) {
+                let action = *rng.choose(&ACTIONS).unwrap();
+                forked.execute_action(action);
+                untouched.execute_action(action);
+                if forked.has_fresh_world() {
+                    forked.fork();
+                    forks += 1;
+                }
+            }
+            forked.update();
+            untouched.update();
+            assert_eq!(pose_checksum(&[&forked]), pose_checksum(&[&untouched]), "the game changed at tick {}", tick);
+        }
+        assert!(forks > 1);
+    }
+
+    // A band through the middle of a piece, high enough to take all of it
+    fn band_through(tetr: &Tetromino) -> (f32, f32) {
+        (tetr.rbh.borrow().center_of_mass().y, 3.0 * BLOCK_DIST)
+    }
+
+    #[test]
+    fn lines_take_control_in_single_player() {
+        let mut game = Game::with_seed(31);
+        game.add_tetromino(0);
+        game.drain_events();
+        // A line at the bottom, nowhere near the piece
+        game.start_clear(vec![(BOTTOM + BLOCK_DIST / 2.0, BLOCK_DIST / 2.0)]);
+        assert!(game.control_object().is_none());
+        assert!(game.drain_events().any(|event| match event { GameEvent::ControlLost => true, _ => false }));
+    }
+
+    #[test]
+    fn lines_only_take_control_of_their_own_pieces_in_coop() {
+        let mut game = Game::with_seed(32);
+        game.set_mode(Mode::Coop);
+        game.add_tetromino(0);
+        game.add_tetromino(1);
+        game.player_object(1).unwrap().rbh.borrow_mut().append_translation(&Vector2::new(0.0, -8.0));
+        let band = band_through(game.player_object(0).unwrap());
+        game.start_clear(vec![band]);
+        assert!(game.player_object(0).is_none());
+        assert!(game.player_object(1).is_some());
+    }
+}
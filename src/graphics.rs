@@ -1,7 +1,6 @@
-use na::OrthographicMatrix3;
-use ncollide::shape;
+use na::{OrthographicMatrix3, Point2, Vector2};
 
-use glium::Surface;
+use glium::{Blend, Surface};
 use glium::backend::Facade;
 use glium::draw_parameters::DrawParameters;
 use glium::index::{IndexBuffer, PrimitiveType};
@@ -11,77 +10,518 @@ use glium_text::{TextSystem, FontTexture, TextDisplay};
 
 use consts::*;
 use game::*;
+use lines::LineDetector;
+use modes::{format_time, format_time_delta, Mode, Outcome};
+use records::{Records, Submission};
+use spectate::GameFrame;
+
+const HUD_LINE_HEIGHT: f32 = 1.2;
+// Number of times a block flashes while its line is being cleared
+const CLEAR_FLASHES: f32 = 3.0;
+const GHOST_FILL_ALPHA: f32 = 0.15;
+const GHOST_OUTLINE_ALPHA: f32 = 0.6;
+const PREDICTION_PATH_ALPHA: f32 = 0.7;
+const PREDICTION_POSE_ALPHA: f32 = 0.35;
+const DEBUG_MARKER_SIZE: f32 = 0.06;
+const DEBUG_NORMAL_LENGTH: f32 = 0.4;
+const DEBUG_VELOCITY_SCALE: f32 = 0.2;
+const DEBUG_LABEL_SIZE: f32 = 0.3;
+const DEBUG_CLEAR_COLOR: [f32; 4] = [0.2, 1.0, 0.2, 0.25];
+const DEBUG_NEAR_MISS_COLOR: [f32; 4] = [1.0, 0.6, 0.1, 0.2];
+const DEBUG_CONTACT_COLOR: [f32; 4] = [1.0, 0.2, 0.2, 1.0];
+const DEBUG_NORMAL_COLOR: [f32; 4] = [1.0, 1.0, 0.2, 1.0];
+const DEBUG_COM_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const DEBUG_VELOCITY_COLOR: [f32; 4] = [0.2, 1.0, 1.0, 1.0];
+const DEBUG_BLOCK_COLOR: [f32; 4] = [1.0, 0.3, 1.0, 1.0];
+const FRAME_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+// Space left and right of the timeline of a replay, in well units
+const TIMELINE_MARGIN: f32 = 0.3;
+const TIMELINE_COLOR: [f32; 4] = [0.3, 0.3, 0.3, 1.0];
+const TIMELINE_PLAYED_COLOR: [f32; 4] = [0.6, 0.6, 0.6, 1.0];
+const TIMELINE_CLEAR_COLOR: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+const TIMELINE_CURSOR_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+// Outlines of the pieces controlled by each player, when there's more than one
+const PLAYER_OUTLINE_COLORS: [[f32; 4]; 2] = [[0.3, 0.8, 1.0, 1.0], [1.0, 0.55, 0.15, 1.0]];
+
+// Optional things drawn on top of the game
+pub struct Overlays {
+    // Where the controlled object would land
+    pub ghost: bool,
+    // Where the controlled object is going, given its current velocity and controls
+    pub prediction: bool,
+    pub prediction_ticks: usize,
+    // Contacts, velocities, block centers and candidate lines
+    pub debug: bool,
+    // Shown at the top of the HUD, e.g. while a bot is playing
+    pub banner: Option<String>,
+}
+
+impl Overlays {
+    pub fn new() -> Self {
+        Overlays {
+            ghost: true,
+            prediction: false,
+            prediction_ticks: PREDICTION_TICKS,
+            debug: false,
+            banner: None,
+        }
+    }
+}
+
+// The part of the window a game is drawn in, as fractions of the window size
+#[derive(Copy, Clone, Debug)]
+pub struct Viewport {
+    pub left: f32,
+    pub bottom: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub fn full() -> Self {
+        Viewport { left: 0.0, bottom: 0.0, width: 1.0, height: 1.0 }
+    }
+
+    // The viewport for one of a number of games side by side
+    pub fn column(index: usize, count: usize) -> Self {
+        let width = 1.0 / count as f32;
+        Viewport { left: index as f32 * width, bottom: 0.0, width: width, height: 1.0 }
+    }
+
+    // Maps normalized device coordinates in the viewport to those in the window
+    fn matrix(&self) -> [[f32; 4]; 4] {
+        [
+            [self.width, 0.0, 0.0, 0.0],
+            [0.0, self.height, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [2.0 * self.left + self.width - 1.0, 2.0 * self.bottom + self.height - 1.0, 0.0, 1.0],
+        ]
+    }
+}
 
 pub struct GraphicsProperties<'a> {
-    proj: [[f32; 4]; 4],
-    program: Program,
-    draw_params: DrawParameters<'a>,
+    pub proj: [[f32; 4]; 4],
+    pub program: Program,
+    pub draw_params: DrawParameters<'a>,
+    viewport: Viewport,
+    // Width of the well that's drawn
+    well_width: f32,
     text_system: TextSystem,
     font: FontTexture,
-    text_proj: [[f32; 4]; 4],
 }
 
 impl<'a> GraphicsProperties<'a> {
     pub fn new<F: Facade>(display: &F) -> Self {
         GraphicsProperties {
-            proj: *OrthographicMatrix3::new(LEFT, RIGHT, BOTTOM, TOP, -1.0, 1.0).as_matrix().as_ref(),
+            proj: well_projection(Viewport::full(), RIGHT - LEFT),
+            viewport: Viewport::full(),
+            well_width: RIGHT - LEFT,
             program: Program::from_source(display,
                                           &include_str!("../res/shaders/tetris.vs"),
                                           &include_str!("../res/shaders/tetris.fs"),
                                           None).unwrap(),
-            draw_params: Default::default(),
+            draw_params: DrawParameters {
+                blend: Blend::alpha_blending(),
+                .. Default::default()
+            },
             text_system: TextSystem::new(display),
             font: FontTexture::new(display, &include_bytes!("../res/fonts/Roboto-Regular.ttf")[..], 100).unwrap(),
-            text_proj: *OrthographicMatrix3::new(-1.0, 23.0, -30.0, 2.0, -1.0, 1.0).as_matrix().as_ref(),
         }
     }
+
+    // Draw everything that follows in the given part of the window
+    pub fn set_viewport(&mut self, viewport: Viewport) {
+        self.viewport = viewport;
+        self.proj = well_projection(viewport, self.well_width);
+    }
+
+    // Fit a well of the given width to the viewport
+    pub fn set_well_width(&mut self, width: f32) {
+        self.well_width = width;
+        self.proj = well_projection(self.viewport, width);
+    }
+
+    // A projection for text, moved into the current viewport
+    fn text_projection(&self, proj: [[f32; 4]; 4]) -> [[f32; 4]; 4] {
+        multiply(self.viewport.matrix(), proj)
+    }
+}
+
+// The projection of a well of the given width onto the viewport
+fn well_projection(viewport: Viewport, width: f32) -> [[f32; 4]; 4] {
+    multiply(viewport.matrix(), *OrthographicMatrix3::new(LEFT, LEFT + width, BOTTOM, TOP, -1.0, 1.0).as_matrix().as_ref())
+}
+
+// Product of two column-major matrices
+fn multiply(a: [[f32; 4]; 4], b: [[f32; 4]; 4]) -> [[f32; 4]; 4] {
+    let mut product = [[0.0; 4]; 4];
+    for column in 0..4 {
+        for row in 0..4 {
+            product[column][row] = (0..4).map(|k| a[k][row] * b[column][k]).sum();
+        }
+    }
+    product
 }
 
 #[derive(Copy, Clone)]
 pub struct Vertex {
-    position: [f32; 2],
-    color: [f32; 3],
+    pub position: [f32; 2],
+    pub color: [f32; 4],
 }
 implement_vertex!(Vertex, position, color);
 
+// Add a convex polygon to a list of triangles
+pub fn push_polygon(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, polygon: &[Point2<f32>], color: [f32; 4]) {
+    let n = vertices.len() as u32;
+    vertices.extend(polygon.iter().map(|pt| Vertex { position: [pt.x, pt.y], color: color }));
+    for i in 1..(polygon.len() as u32 - 1) {
+        indices.extend_from_slice(&[n, n + i, n + i + 1]);
+    }
+}
+
+// Add the edges of a polygon to a list of lines
+pub fn push_outline(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, polygon: &[Point2<f32>], color: [f32; 4]) {
+    let n = vertices.len() as u32;
+    vertices.extend(polygon.iter().map(|pt| Vertex { position: [pt.x, pt.y], color: color }));
+    let len = polygon.len() as u32;
+    for i in 0..len {
+        indices.extend_from_slice(&[n + i, n + (i + 1) % len]);
+    }
+}
+
+pub fn draw_mesh<S: Surface, F: Facade>(display: &F, target: &mut S, props: &GraphicsProperties,
+                                        vertices: &[Vertex], indices: &[u32], primitive: PrimitiveType) {
+    if indices.is_empty() { return; }
+    let uniforms = uniform! { proj: props.proj };
+    let vb = VertexBuffer::new(display, vertices).unwrap();
+    let ib = IndexBuffer::new(display, primitive, indices).unwrap();
+    target.draw(&vb, &ib, &props.program, &uniforms, &props.draw_params).unwrap();
+}
+
 impl Game {
-    pub fn draw<S: Surface, F: Facade>(&self, display: &F, target: &mut S, props: &GraphicsProperties) {
+    pub fn draw<S: Surface, F: Facade>(&self, display: &F, target: &mut S, props: &GraphicsProperties,
+                                       records: &Records, overlays: &Overlays) {
         // Draw the blocks
         let mut vertices = vec![];
         let mut indices: Vec<u32> = vec![];
-        let mut n = 0;
 
-        for Tetromino { rbh, color } in self.tetrominos().cloned() {
-            let rb = rbh.borrow();
-            let &iso = rb.position();
-            let inner_shapes = rb.shape().as_shape::<shape::Compound<_, _>>().unwrap().shapes();
-
-            for &(inner_iso, _) in inner_shapes.iter() {
-                let transform = iso * inner_iso;
-                vertices.extend(block(BLOCK_SIZE / 2.0, CORNER_RADIUS, EDGES_PER_CORNER)
-                                .into_iter()
-                                .map(|pt| {
-                                    let pos = transform * pt;
-                                    Vertex { position: [pos.x, pos.y], color: color }
-                                }));
-                for i in 1..(VERTS_PER_BLOCK - 1) {
-                    indices.extend_from_slice(&[n, n + i, n + i + 1]);
+        let clear_progress = self.clear_progress().unwrap_or(0.0);
+
+        for tetr in self.tetrominos() {
+            let iso = *tetr.rbh.borrow().position();
+
+            for (block, &state) in tetr.blocks().iter().zip(tetr.states.iter()) {
+                let (scale, [r, g, b]) = match state {
+                    BlockState::Normal => (1.0, tetr.color),
+                    // Blocks in a cleared line flash and shrink until they disappear
+                    BlockState::Clearing => {
+                        let flash = 0.5 + 0.5 * (clear_progress * CLEAR_FLASHES * 2.0 * ::std::f32::consts::PI).cos();
+                        let color = [tetr.color[0] + (1.0 - tetr.color[0]) * flash,
+                                     tetr.color[1] + (1.0 - tetr.color[1]) * flash,
+                                     tetr.color[2] + (1.0 - tetr.color[2]) * flash];
+                        (1.0 - clear_progress, color)
+                    },
+                };
+                let center = block.0.translation.to_point();
+                let outline: Vec<_> = block_outline(block).into_iter()
+                    .map(|pt| iso * (center + (pt - center) * scale))
+                    .collect();
+                push_polygon(&mut vertices, &mut indices, &outline, [r, g, b, 1.0]);
+            }
+        }
+        draw_mesh(display, target, props, &vertices, &indices, PrimitiveType::TrianglesList);
+
+        // With more than one player, every player's piece is outlined in their own color
+        if self.players() > 1 {
+            let (mut vertices, mut indices) = (vec![], vec![]);
+            for player in 0..self.players() {
+                if let Some(tetr) = self.player_object(player) {
+                    let iso = *tetr.rbh.borrow().position();
+                    for block in tetr.blocks() {
+                        let outline: Vec<_> = block_outline(&block).into_iter().map(|pt| iso * pt).collect();
+                        push_outline(&mut vertices, &mut indices, &outline, PLAYER_OUTLINE_COLORS[player % PLAYER_OUTLINE_COLORS.len()]);
+                    }
                 }
-                n += VERTS_PER_BLOCK;
             }
+            draw_mesh(display, target, props, &vertices, &indices, PrimitiveType::LinesList);
         }
 
-        let uniforms = uniform! { proj: props.proj };
-        let vb = VertexBuffer::new(display, &vertices[..]).unwrap();
-        let ib = IndexBuffer::new(display, PrimitiveType::TrianglesList, &indices[..]).unwrap();
-        target.draw(&vb, &ib, &props.program, &uniforms, &props.draw_params).unwrap();
+        // Draw a translucent outline where each controlled object would land
+        if overlays.ghost {
+            for player in 0..self.players() {
+                if let (Some(tetr), Some(ghost)) = (self.player_object(player), self.ghost_pose(player)) {
+                    let [r, g, b] = tetr.color;
+                    let (mut fill_vertices, mut fill_indices) = (vec![], vec![]);
+                    let (mut line_vertices, mut line_indices) = (vec![], vec![]);
+                    for block in tetr.blocks() {
+                        let outline: Vec<_> = block_outline(&block).into_iter().map(|pt| ghost * pt).collect();
+                        push_polygon(&mut fill_vertices, &mut fill_indices, &outline, [r, g, b, GHOST_FILL_ALPHA]);
+                        push_outline(&mut line_vertices, &mut line_indices, &outline, [r, g, b, GHOST_OUTLINE_ALPHA]);
+                    }
+                    draw_mesh(display, target, props, &fill_vertices, &fill_indices, PrimitiveType::TrianglesList);
+                    draw_mesh(display, target, props, &line_vertices, &line_indices, PrimitiveType::LinesList);
+                }
+            }
+        }
+
+        // Draw the predicted path of each controlled object as a fading trail
+        if overlays.prediction {
+            for player in 0..self.players() {
+                if let (Some(tetr), Some(prediction)) = (self.player_object(player), self.predict(player, overlays.prediction_ticks)) {
+                    let [r, g, b] = tetr.color;
+                    let (mut vertices, mut indices) = (vec![], vec![]);
+                    for (i, pair) in prediction.path.windows(2).enumerate() {
+                        let alpha = PREDICTION_PATH_ALPHA * (1.0 - i as f32 / prediction.path.len() as f32);
+                        let n = vertices.len() as u32;
+                        vertices.push(Vertex { position: [pair[0].x, pair[0].y], color: [r, g, b, alpha] });
+                        vertices.push(Vertex { position: [pair[1].x, pair[1].y], color: [r, g, b, alpha] });
+                        indices.extend_from_slice(&[n, n + 1]);
+                    }
+                    for block in tetr.blocks() {
+                        let outline: Vec<_> = block_outline(&block).into_iter().map(|pt| prediction.final_pose * pt).collect();
+                        push_outline(&mut vertices, &mut indices, &outline, [r, g, b, PREDICTION_POSE_ALPHA]);
+                    }
+                    draw_mesh(display, target, props, &vertices, &indices, PrimitiveType::LinesList);
+                }
+            }
+        }
+
+        if overlays.debug {
+            self.draw_debug(display, target, props);
+        }
 
         // Draw text
-        let text = TextDisplay::new(&props.text_system, &props.font, &format!("Score: {}", self.score()));
-        ::glium_text::draw(&text, &props.text_system, target, props.text_proj, (1.0, 1.0, 1.0, 1.0));
+        let mut hud = vec![
+            format!("{}  Score: {}", self.mode().name(), self.score()),
+            format!("Level: {} ({} lines)", self.level(), self.lines()),
+        ];
+        if self.rewinds() > 0 {
+            hud.push(format!("Rewinds: {}", self.rewinds()));
+        }
+        if let Some(ref banner) = overlays.banner {
+            hud.insert(0, banner.clone());
+        }
+        if let Mode::Sprint = self.mode() {
+            hud.push(format!("Lines: {}/{}  Time: {}", self.lines(), SPRINT_LINES, format_time(self.time_played())));
+            match (records.last_submission(), records.sprint_best()) {
+                (Some(&Submission { outcome: Outcome::SprintTime(time), previous_best: Some(best), .. }), _) => {
+                    let verdict = if time < best { "New personal best!" } else { "" };
+                    hud.push(format!("Finished in {} ({} vs PB) {}", format_time(time), format_time_delta(time, best), verdict));
+                },
+                (Some(&Submission { outcome: Outcome::SprintTime(time), previous_best: None, .. }), _) => {
+                    hud.push(format!("Finished in {}. First personal best!", format_time(time)));
+                },
+                (None, Some(best)) => hud.push(format!("Personal best: {}", format_time(best))),
+                (None, None) => {},
+            }
+        }
+        if let Mode::Ultra = self.mode() {
+            hud.push(format!("Time left: {}", format_time(self.time_left().unwrap())));
+            if self.settling() {
+                hud.push("Time's up! Waiting for everything to settle...".to_owned());
+            }
+            if let Some(&Submission { outcome: Outcome::UltraScore(score), rank, .. }) = records.last_submission() {
+                hud.push(match rank {
+                    Some(rank) => format!("Final score: {} (#{} in the high scores)", score, rank + 1),
+                    None => format!("Final score: {}", score),
+                });
+            }
+            if !records.ultra_scores().is_empty() {
+                hud.push("High scores:".to_owned());
+                for (rank, score) in records.ultra_scores().iter().enumerate() {
+                    hud.push(format!("  {}. {}", rank + 1, score));
+                }
+            }
+        }
+        if let Mode::Survival = self.mode() {
+            hud.push(format!("Survived: {}", format_time(self.time_played())));
+            if let Some(next) = self.next_garbage() {
+                hud.push(format!("Next garbage row in {}", format_time(next)));
+            }
+            match (records.last_submission(), records.survival_best()) {
+                (Some(&Submission { outcome: Outcome::SurvivalTime(time), previous_best, .. }), _) => {
+                    let verdict = match previous_best {
+                        Some(best) if time <= best => format!("(best: {})", format_time(best)),
+                        _ => "New personal best!".to_owned(),
+                    };
+                    hud.push(format!("Topped out after {} {}", format_time(time), verdict));
+                },
+                (_, Some(best)) => hud.push(format!("Personal best: {}", format_time(best))),
+                _ => {},
+            }
+        }
+        if let Mode::Versus | Mode::Coop = self.mode() {
+            if let Some(Outcome::ToppedOut(time)) = self.outcome() {
+                hud.push(format!("Topped out after {}", format_time(time)));
+            }
+        }
+        if self.line_detector() != LineDetector::BlockCenters {
+            hud.push(format!("Line detection: {}", self.line_detector().name()));
+        }
+        if self.split_mode() != SplitMode::Remove {
+            hud.push("Blocks are cut along cleared lines".to_owned());
+        }
+        if let Some(countdown) = self.countdown() {
+            hud.push(format!("Starting in {}...", countdown.as_secs() + 1));
+        }
+        if let Some(event) = self.recent_score_event() {
+            hud.push(format!("+{} ({})", event.total(), event.describe()));
+        }
+        for (line, text) in hud.iter().enumerate() {
+            draw_text(target, props, line, text);
+        }
+    }
+}
+
+impl GameFrame {
+    // Draw a game that's streamed by another instance, together with a short HUD
+    pub fn draw<S: Surface, F: Facade>(&self, display: &F, target: &mut S, props: &GraphicsProperties, tick: u64) {
+        let (mut vertices, mut indices) = (vec![], vec![]);
+        let (mut line_vertices, mut line_indices) = (vec![], vec![]);
+        for body in self.bodies.iter() {
+            let (r, g, b) = (body.color[0], body.color[1], body.color[2]);
+            for block in body.blocks.iter() {
+                let outline = body.world_outline(block);
+                // Blocks in a cleared line are shown lighter
+                let color = if block.clearing { [0.5 + r / 2.0, 0.5 + g / 2.0, 0.5 + b / 2.0, 1.0] } else { [r, g, b, 1.0] };
+                push_polygon(&mut vertices, &mut indices, &outline, color);
+                if let Some(player) = body.player {
+                    push_outline(&mut line_vertices, &mut line_indices, &outline, PLAYER_OUTLINE_COLORS[player % PLAYER_OUTLINE_COLORS.len()]);
+                }
+            }
+        }
+        draw_mesh(display, target, props, &vertices, &indices, PrimitiveType::TrianglesList);
+        draw_mesh(display, target, props, &line_vertices, &line_indices, PrimitiveType::LinesList);
+
+        let hud = [
+            format!("{}  Score: {}", self.mode, self.score),
+            format!("Level: {} ({} lines)", self.level, self.lines),
+            format!("Spectating, tick {}", tick),
+        ];
+        for (line, text) in hud.iter().enumerate() {
+            draw_text(target, props, line, text);
+        }
     }
 }
 
+impl Game {
+    // Physics debug overlay: candidate lines, contacts, centers of mass, velocities and block centers
+    fn draw_debug<S: Surface, F: Facade>(&self, display: &F, target: &mut S, props: &GraphicsProperties) {
+        let objects: Vec<_> = self.tetrominos().cloned().collect();
+        let bands = self.line_detector().diagnose(&objects, self.columns());
+
+        // Highlight the bands the line detector is looking at
+        let (mut vertices, mut indices) = (vec![], vec![]);
+        for band in bands.iter() {
+            let (y1, y2) = (band.center - band.half_height, band.center + band.half_height);
+            let right = self.well_right();
+            let rect = [Point2::new(LEFT, y1), Point2::new(right, y1), Point2::new(right, y2), Point2::new(LEFT, y2)];
+            push_polygon(&mut vertices, &mut indices, &rect,
+                         if band.clears { DEBUG_CLEAR_COLOR } else { DEBUG_NEAR_MISS_COLOR });
+        }
+        draw_mesh(display, target, props, &vertices, &indices, PrimitiveType::TrianglesList);
+
+        let (mut vertices, mut indices) = (vec![], vec![]);
+        let line = |vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, from: Point2<f32>, to: Point2<f32>, color| {
+            push_outline(vertices, indices, &[from, to], color);
+        };
+        let cross = |vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, at: Point2<f32>, color| {
+            let s = DEBUG_MARKER_SIZE;
+            push_outline(vertices, indices, &[at + Vector2::new(-s, -s), at + Vector2::new(s, s)], color);
+            push_outline(vertices, indices, &[at + Vector2::new(-s, s), at + Vector2::new(s, -s)], color);
+        };
+        for &(position, normal) in self.contacts() {
+            cross(&mut vertices, &mut indices, position, DEBUG_CONTACT_COLOR);
+            line(&mut vertices, &mut indices, position, position + normal * DEBUG_NORMAL_LENGTH, DEBUG_NORMAL_COLOR);
+        }
+        for tetr in objects.iter() {
+            let rb = tetr.rbh.borrow();
+            let &iso = rb.position();
+            let com = *rb.center_of_mass();
+            cross(&mut vertices, &mut indices, com, DEBUG_COM_COLOR);
+            line(&mut vertices, &mut indices, com, com + rb.lin_vel() * DEBUG_VELOCITY_SCALE, DEBUG_VELOCITY_COLOR);
+            for &(inner_iso, _) in tetr.blocks().iter() {
+                cross(&mut vertices, &mut indices, iso * inner_iso.translation.to_point(), DEBUG_BLOCK_COLOR);
+            }
+        }
+        draw_mesh(display, target, props, &vertices, &indices, PrimitiveType::LinesList);
+
+        // Label each band with what the detector thinks of it
+        for band in bands.iter() {
+            let label = match band.coverage {
+                Some(coverage) => format!("{} blocks, {:.0}% covered", band.blocks, coverage * 100.0),
+                None => format!("{}/{} blocks, spread {:.3}/{:.3}", band.blocks, self.columns(), band.spread, LINE_THRESHOLD),
+            };
+            draw_text_at(target, props, Point2::new(LEFT + 0.1, band.center + band.half_height), DEBUG_LABEL_SIZE, &label);
+        }
+    }
+}
+
+// Draw text with its bottom left corner at the given position in the well
+fn draw_text_at<S: Surface>(target: &mut S, props: &GraphicsProperties, position: Point2<f32>, size: f32, text: &str) {
+    let text = TextDisplay::new(&props.text_system, &props.font, text);
+    let (sx, sy) = (2.0 / props.well_width, 2.0 / (TOP - BOTTOM));
+    let proj = [
+        [size * sx, 0.0, 0.0, 0.0],
+        [0.0, size * sy, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [(position.x - LEFT) * sx - 1.0, (position.y - BOTTOM) * sy - 1.0, 0.0, 1.0],
+    ];
+    ::glium_text::draw(&text, &props.text_system, target, props.text_projection(proj), (1.0, 1.0, 1.0, 1.0));
+}
+
+// Draw a line of text in the HUD. Line 0 is at the top of the screen.
+fn draw_text<S: Surface>(target: &mut S, props: &GraphicsProperties, line: usize, text: &str) {
+    let text = TextDisplay::new(&props.text_system, &props.font, text);
+    // Moving the projection up moves the text down
+    let offset = line as f32 * HUD_LINE_HEIGHT;
+    // Text keeps its size in wider wells
+    let right = -1.0 + 24.0 * props.well_width / (RIGHT - LEFT);
+    let proj = *OrthographicMatrix3::new(-1.0, right, -30.0 + offset, 2.0 + offset, -1.0, 1.0).as_matrix().as_ref();
+    ::glium_text::draw(&text, &props.text_system, target, props.text_projection(proj), (1.0, 1.0, 1.0, 1.0));
+}
+
+// Draw the edges of the well, to separate games that are side by side
+pub fn draw_frame<S: Surface, F: Facade>(display: &F, target: &mut S, props: &GraphicsProperties) {
+    let (mut vertices, mut indices) = (vec![], vec![]);
+    let right = LEFT + props.well_width;
+    let corners = [Point2::new(LEFT, BOTTOM), Point2::new(right, BOTTOM), Point2::new(right, TOP), Point2::new(LEFT, TOP)];
+    push_outline(&mut vertices, &mut indices, &corners, FRAME_COLOR);
+    draw_mesh(display, target, props, &vertices, &indices, PrimitiveType::LinesList);
+}
+
+// Draw the timeline of a replay over the whole viewport: how much has been played, and a mark for
+// every line clear, which is taller the more lines were cleared at once
+pub fn draw_timeline<S: Surface, F: Facade>(display: &F, target: &mut S, props: &GraphicsProperties,
+                                            position: u64, length: u64, clears: &[(u64, usize)]) {
+    let (left, right) = (LEFT + TIMELINE_MARGIN, LEFT + props.well_width - TIMELINE_MARGIN);
+    let x = |step: u64| left + (right - left) * step as f32 / length.max(1) as f32;
+    let middle = (BOTTOM + TOP) / 2.0;
+    let (mut vertices, mut indices) = (vec![], vec![]);
+    push_rectangle(&mut vertices, &mut indices, Point2::new(left, middle - 1.0), Point2::new(right, middle + 1.0), TIMELINE_COLOR);
+    push_rectangle(&mut vertices, &mut indices, Point2::new(left, middle - 1.0), Point2::new(x(position), middle + 1.0), TIMELINE_PLAYED_COLOR);
+    for &(step, lines) in clears.iter() {
+        let half_height = (1.5 * lines as f32).min((TOP - BOTTOM) / 2.0);
+        push_rectangle(&mut vertices, &mut indices, Point2::new(x(step) - 0.02, middle - half_height),
+                       Point2::new(x(step) + 0.02, middle + half_height), TIMELINE_CLEAR_COLOR);
+    }
+    push_rectangle(&mut vertices, &mut indices, Point2::new(x(position) - 0.04, BOTTOM),
+                   Point2::new(x(position) + 0.04, TOP), TIMELINE_CURSOR_COLOR);
+    draw_mesh(display, target, props, &vertices, &indices, PrimitiveType::TrianglesList);
+}
+
+// The step of a replay at a horizontal position on its timeline, as a fraction of the viewport width
+pub fn timeline_step(props: &GraphicsProperties, fraction: f32, length: u64) -> u64 {
+    let (left, right) = (LEFT + TIMELINE_MARGIN, LEFT + props.well_width - TIMELINE_MARGIN);
+    let x = LEFT + fraction * props.well_width;
+    (((x - left) / (right - left)).max(0.0).min(1.0) * length as f32).round() as u64
+}
+
+fn push_rectangle(vertices: &mut Vec<Vertex>, indices: &mut Vec<u32>, min: Point2<f32>, max: Point2<f32>, color: [f32; 4]) {
+    let corners = [min, Point2::new(max.x, min.y), max, Point2::new(min.x, max.y)];
+    push_polygon(vertices, indices, &corners, color);
+}
+
 pub fn show_loading_screen<F: Facade, S: Surface>(_display: &F, target: &mut S) {
     target.clear_color(1.0, 1.0, 1.0, 1.0);
 }
@@ -0,0 +1,50 @@
+use na::Vector2;
+
+use consts::*;
+
+// One row of the difficulty schedule.
+pub struct Level {
+    // Total number of cleared lines needed to reach this level
+    pub lines: usize,
+    // Downward acceleration
+    pub gravity: f32,
+    // Time after which the next piece is spawned automatically
+    pub auto_spawn_ms: u64,
+}
+
+impl Level {
+    pub fn gravity(&self) -> Vector2<f32> {
+        Vector2::new(0.0, -self.gravity)
+    }
+}
+
+#[derive(Clone)]
+pub struct Levels {
+    lines: usize,
+    level: usize,
+}
+
+impl Levels {
+    pub fn new() -> Self {
+        Levels { lines: 0, level: 0 }
+    }
+
+    // Level number as shown to the player
+    pub fn number(&self) -> usize { self.level + 1 }
+    pub fn lines(&self) -> usize { self.lines }
+    pub fn current(&self) -> &'static Level { &LEVELS[self.level] }
+
+    // Register cleared lines. Returns true if this advanced the level.
+    pub fn lines_cleared(&mut self, lines: usize) -> bool {
+        self.lines += lines;
+        let old_level = self.level;
+        while self.level + 1 < LEVELS.len() && LEVELS[self.level + 1].lines <= self.lines {
+            self.level += 1;
+        }
+        self.level != old_level
+    }
+
+    pub fn reset(&mut self) {
+        *self = Levels::new();
+    }
+}
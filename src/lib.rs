@@ -0,0 +1,34 @@
+#![feature(slice_patterns, advanced_slice_patterns)]
+
+extern crate num;
+extern crate nalgebra as na;
+extern crate ncollide;
+extern crate nphysics2d;
+extern crate rand;
+#[cfg(feature="audio")]
+extern crate rodio;
+
+#[macro_use] extern crate glium;
+extern crate glium_text;
+
+pub mod ai;
+pub mod audio;
+pub mod clipping;
+pub mod consts;
+pub mod controls;
+pub mod env;
+pub mod events;
+pub mod game;
+pub mod graphics;
+pub mod levels;
+pub mod lines;
+pub mod modes;
+pub mod netcode;
+pub mod particles;
+pub mod prediction;
+pub mod records;
+pub mod replay;
+pub mod scoring;
+pub mod spectate;
+pub mod verify;
+pub mod versus;
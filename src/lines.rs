@@ -0,0 +1,279 @@
+use std::cmp::Ordering;
+
+use na::{Point2, Rotate};
+use ncollide::shape;
+
+use consts::*;
+use game::{block_outline, Tetromino};
+
+// A horizontal band that is, or almost is, a completed line
+#[derive(Clone, Debug)]
+pub struct Band {
+    pub center: f32,
+    pub half_height: f32,
+    // Number of block centers in the band
+    pub blocks: usize,
+    // Height difference between the lowest and highest block center that count towards the line
+    pub spread: f32,
+    // Fraction of the band covered by blocks (area coverage detector only)
+    pub coverage: Option<f32>,
+    pub clears: bool,
+}
+
+// The algorithm used to find completed lines.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LineDetector {
+    // Look for a line's worth of block centers within LINE_THRESHOLD of each other
+    BlockCenters,
+    // Look for horizontal bands of which at least the given fraction is covered by blocks
+    AreaCoverage(f32),
+}
+
+impl LineDetector {
+    pub fn name(&self) -> String {
+        match *self {
+            LineDetector::BlockCenters => "block centers".to_owned(),
+            LineDetector::AreaCoverage(coverage) => format!("{:.0}% coverage", coverage * 100.0),
+        }
+    }
+
+    // Find all completed lines in a well of the given width, as a list of (center, half height) pairs
+    pub fn find_lines(&self, objects: &[Tetromino], blocks_per_line: usize) -> Vec<(f32, f32)> {
+        match *self {
+            LineDetector::BlockCenters => block_center_lines(objects, blocks_per_line),
+            LineDetector::AreaCoverage(coverage) => area_coverage_lines(objects, blocks_per_line, coverage),
+        }
+    }
+
+    // All bands considered by the detector that have at least half the blocks of a line,
+    // including the ones that are cleared.
+    pub fn diagnose(&self, objects: &[Tetromino], blocks_per_line: usize) -> Vec<Band> {
+        let heights = block_heights(objects);
+        let lines = self.find_lines(objects, blocks_per_line);
+        let mut bands = match *self {
+            LineDetector::BlockCenters => {
+                // Group block centers that are close together
+                let mut groups: Vec<Vec<f32>> = vec![];
+                for &y in heights.iter() {
+                    let close = groups.last().map_or(false, |group| y - group[group.len() - 1] < BLOCK_DIST / 2.0);
+                    if close {
+                        groups.last_mut().unwrap().push(y);
+                    } else {
+                        groups.push(vec![y]);
+                    }
+                }
+                groups.into_iter()
+                    .filter(|group| group.len() >= blocks_per_line / 2)
+                    .map(|group| {
+                        // The tightest set of blocks_per_line blocks, or all of them if there aren't enough
+                        let (lo, hi) = if group.len() >= blocks_per_line {
+                            group.iter().cloned().zip(group.iter().cloned().skip(blocks_per_line - 1))
+                                .fold((group[0], ::std::f32::INFINITY), |best, (y1, y2)| {
+                                    if y2 - y1 < best.1 - best.0 { (y1, y2) } else { best }
+                                })
+                        } else {
+                            (group[0], group[group.len() - 1])
+                        };
+                        Band {
+                            center: (lo + hi) / 2.0,
+                            half_height: ((hi - lo) / 2.0).max(LINE_THRESHOLD / 2.0),
+                            blocks: group.len(),
+                            spread: hi - lo,
+                            coverage: None,
+                            clears: false,
+                        }
+                    }).collect::<Vec<_>>()
+            },
+            LineDetector::AreaCoverage(_) => {
+                // Bands that are covered better than both of their neighbours
+                let coverages = band_coverages(objects, blocks_per_line);
+                (1..coverages.len().saturating_sub(1))
+                    .filter(|&i| coverages[i].1 >= coverages[i - 1].1 && coverages[i].1 > coverages[i + 1].1)
+                    .filter(|&i| coverages[i].1 >= 0.5)
+                    .map(|i| {
+                        let (center, coverage) = coverages[i];
+                        let inside: Vec<f32> = heights.iter().cloned()
+                            .filter(|&y| (y - center).abs() <= BLOCK_DIST / 2.0)
+                            .collect();
+                        Band {
+                            center: center,
+                            half_height: BLOCK_DIST / 2.0,
+                            blocks: inside.len(),
+                            spread: inside.last().map_or(0.0, |&hi| hi - inside[0]),
+                            coverage: Some(coverage),
+                            clears: false,
+                        }
+                    }).collect::<Vec<_>>()
+            },
+        };
+        // Mark the bands that are actually cleared
+        for band in bands.iter_mut() {
+            band.clears = lines.iter().any(|&(y, _)| (y - band.center).abs() < band.half_height.max(LINE_THRESHOLD));
+        }
+        bands
+    }
+}
+
+// Orders floats, with NaN after everything else, so that a broken body can't make a sort panic
+fn compare(a: f32, b: f32) -> Ordering {
+    a.partial_cmp(&b).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+// The y-coordinate of all individual blocks, sorted
+fn block_heights(objects: &[Tetromino]) -> Vec<f32> {
+    let mut block_heights: Vec<f32> = objects.iter()
+        .flat_map(|tetr| {
+            let orig_iso = *tetr.rbh.borrow().position();
+            tetr.rbh.borrow().shape()
+                .as_shape::<shape::Compound<_, _>>().unwrap()
+                .shapes().iter()
+                .map(|&(iso, _)| (orig_iso.translation + orig_iso.rotation.rotate(&iso.translation)).y)
+                .collect::<Vec<_>>().into_iter()
+        }).collect();
+    block_heights.sort_by(|&y1, &y2| compare(y1, y2));
+    block_heights
+}
+
+fn block_center_lines(objects: &[Tetromino], blocks_per_line: usize) -> Vec<(f32, f32)> {
+    // Collect the y-coordinate of all individual blocks, and sort them.
+    let block_heights = block_heights(objects);
+    // All groups of blocks_per_line blocks that are at approximately the same height
+    // form a line. Each element of `line_heights` represent a horizontal line
+    // that will be deleted.
+    block_heights.iter().cloned()
+        .zip(block_heights.iter().cloned().skip(blocks_per_line - 1))
+        .filter(|&(y1, y2)| y2 - y1 < LINE_THRESHOLD)
+        .fold((vec![], 0.0), |(mut lines, min_y), (y1, y2)| {
+            if y1 <= min_y {
+                // This completed line overlaps with another completed line
+                // This means there's more than blocks_per_line blocks located
+                // on the same horizontal line - this should not happen for
+                // reasonable values of blocks_per_line!
+                return (lines, min_y);
+            }
+            let center = (y2 + y1) / 2.0;
+            let threshold = (y2 - y1) / 2.0;
+            lines.push((center, threshold));
+            (lines, y2)
+        }).0
+}
+
+// The outlines of all blocks in world coordinates
+pub fn block_hulls(objects: &[Tetromino]) -> Vec<Vec<Point2<f32>>> {
+    objects.iter()
+        .flat_map(|tetr| {
+            let iso = *tetr.rbh.borrow().position();
+            tetr.blocks().iter()
+                .map(|block| block_outline(block).into_iter().map(|pt| iso * pt).collect::<Vec<_>>())
+                .collect::<Vec<_>>().into_iter()
+        }).collect()
+}
+
+// The interval in which a horizontal line at height y intersects a convex polygon
+pub fn hull_interval(hull: &[Point2<f32>], y: f32) -> Option<(f32, f32)> {
+    let mut interval: Option<(f32, f32)> = None;
+    for (i, &p1) in hull.iter().enumerate() {
+        let p2 = hull[(i + 1) % hull.len()];
+        if (p1.y <= y) == (p2.y <= y) { continue; }
+        let x = p1.x + (y - p1.y) * (p2.x - p1.x) / (p2.y - p1.y);
+        interval = Some(match interval {
+            Some((x1, x2)) => (x1.min(x), x2.max(x)),
+            None => (x, x),
+        });
+    }
+    interval
+}
+
+// The fraction of a horizontal line at height y that is covered by the given hulls
+fn scanline_coverage(hulls: &[Vec<Point2<f32>>], y: f32, blocks_per_line: usize) -> f32 {
+    let mut intervals: Vec<(f32, f32)> = hulls.iter().filter_map(|hull| hull_interval(hull, y)).collect();
+    intervals.sort_by(|a, b| compare(a.0, b.0));
+    // Merge overlapping intervals and sum their lengths
+    let (covered, last) = intervals.into_iter()
+        .fold((0.0, None), |(covered, current): (f32, Option<(f32, f32)>), (x1, x2)| match current {
+            Some((c1, c2)) if x1 <= c2 => (covered, Some((c1, c2.max(x2)))),
+            Some((c1, c2)) => (covered + c2 - c1, Some((x1, x2))),
+            None => (covered, Some((x1, x2))),
+        });
+    let covered = covered + last.map_or(0.0, |(x1, x2)| x2 - x1);
+    // A perfectly filled line still has small gaps between the blocks
+    (covered / (blocks_per_line as f32 * BLOCK_SIZE)).min(1.0)
+}
+
+// The coverage of every band of height BLOCK_DIST, starting from the bottom of the well in
+// steps of BLOCK_DIST / COVERAGE_SCANLINES. Returns a list of (band center, coverage) pairs.
+pub fn band_coverages(objects: &[Tetromino], blocks_per_line: usize) -> Vec<(f32, f32)> {
+    let hulls = block_hulls(objects);
+    let step = BLOCK_DIST / COVERAGE_SCANLINES as f32;
+    let n_scanlines = ((TOP - BOTTOM) / step) as usize;
+    // Every scanline is evaluated once, and shared between all bands containing it
+    let scanlines: Vec<f32> = (0..n_scanlines)
+        .map(|i| scanline_coverage(&hulls, BOTTOM + (i as f32 + 0.5) * step, blocks_per_line))
+        .collect();
+    scanlines.windows(COVERAGE_SCANLINES)
+        .enumerate()
+        .map(|(i, window)| {
+            let center = BOTTOM + i as f32 * step + BLOCK_DIST / 2.0;
+            (center, window.iter().fold(0.0, |sum, &c| sum + c) / COVERAGE_SCANLINES as f32)
+        }).collect()
+}
+
+fn area_coverage_lines(objects: &[Tetromino], blocks_per_line: usize, coverage: f32) -> Vec<(f32, f32)> {
+    let mut candidates: Vec<_> = band_coverages(objects, blocks_per_line).into_iter()
+        .filter(|&(_, c)| c >= coverage)
+        .collect();
+    // Prefer the best covered bands, and never clear two overlapping bands
+    candidates.sort_by(|a, b| compare(b.1, a.1));
+    let mut lines: Vec<(f32, f32)> = vec![];
+    for (center, _) in candidates {
+        if lines.iter().all(|&(other, _)| (other - center).abs() >= BLOCK_DIST) {
+            lines.push((center, BLOCK_DIST / 2.0));
+        }
+    }
+    lines.sort_by(|a, b| compare(a.0, b.0));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+
+    use na::Vector2;
+
+    use consts::*;
+    use game::{create_world, Tetromino};
+    use super::{band_coverages, compare, LineDetector};
+
+    const BAR: [[usize; 2]; 4] = [[0, 0], [1, 0], [2, 0], [3, 0]];
+
+    #[test]
+    fn area_coverage_reports_rows_over_the_threshold() {
+        let mut world = create_world(RIGHT);
+        let (full, partial) = (BOTTOM + 1.5 * BLOCK_DIST, BOTTOM + 4.5 * BLOCK_DIST);
+        // Three bars fill a row, two bars leave a third of the other one open
+        let bars = vec![(2.0, full), (6.0, full), (10.0, full), (2.0, partial), (6.0, partial)];
+        let objects: Vec<Tetromino> = bars.into_iter()
+            .map(|(x, y)| Tetromino::new(&mut world, &BAR, 0.0, Vector2::new(LEFT + x * BLOCK_DIST, y), [1.0, 1.0, 1.0]))
+            .collect();
+        let coverage = band_coverages(&objects, BLOCKS_PER_LINE).into_iter()
+            .filter(|&(center, _)| (center - partial).abs() < BLOCK_DIST / 2.0)
+            .fold(0.0, |max, (_, c)| c.max(max));
+        assert!(coverage > 0.5 && coverage < 0.8);
+
+        let below = LineDetector::AreaCoverage(coverage - 0.01).find_lines(&objects, BLOCKS_PER_LINE);
+        assert_eq!(below.len(), 2);
+        assert!((below[0].0 - full).abs() < BLOCK_DIST / 4.0);
+        assert!((below[1].0 - partial).abs() < BLOCK_DIST / 4.0);
+        let above = LineDetector::AreaCoverage(coverage + 0.01).find_lines(&objects, BLOCKS_PER_LINE);
+        assert_eq!(above.len(), 1);
+        assert!((above[0].0 - full).abs() < BLOCK_DIST / 4.0);
+    }
+
+    #[test]
+    fn nan_sorts_last() {
+        let mut values = vec![2.0, f32::NAN, 1.0, f32::NAN, 0.0];
+        values.sort_by(|&a, &b| compare(a, b));
+        assert_eq!(&values[..3], &[0.0, 1.0, 2.0]);
+        assert!(values[3].is_nan() && values[4].is_nan());
+    }
+}
@@ -1,34 +1,78 @@
-#![feature(slice_patterns, advanced_slice_patterns)]
-
-extern crate num;
-extern crate nalgebra as na;
-extern crate ncollide;
-extern crate nphysics2d;
-extern crate rand;
-
-#[macro_use] extern crate glium;
-extern crate glium_text;
+extern crate glium;
+extern crate gliumtetris;
 
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::time::{Duration, Instant};
 
 use glium::{DisplayBuild, Surface};
+use glium::backend::glutin_backend::GlutinFacade;
 
-mod consts;
-mod controls;
-mod game;
-mod graphics;
+use gliumtetris::consts;
+use gliumtetris::ai::{Bot, Role};
+use gliumtetris::audio::{Audio, Output};
+use gliumtetris::controls::{Action, Command, Controls};
+use gliumtetris::events::GameEvent;
+use gliumtetris::game::*;
+use gliumtetris::graphics::{draw_frame, draw_timeline, show_loading_screen, timeline_step, GraphicsProperties, Overlays, Viewport};
+use gliumtetris::modes::Mode;
+use gliumtetris::netcode::{Handshake, Lockstep};
+use gliumtetris::particles::ParticleSystem;
+use gliumtetris::records::Records;
+use gliumtetris::replay::{InputLog, Recorder, Replay};
+use gliumtetris::spectate::SpectatorServer;
+use gliumtetris::versus::{RoundEnd, Versus};
 
-use controls::Controls;
-use game::*;
-use graphics::{show_loading_screen, GraphicsProperties};
+const WINDOW_WIDTH: u32 = 600;
+const WINDOW_HEIGHT: u32 = 800;
+// Height of the timeline below the well when watching a replay
+const TIMELINE_HEIGHT: u32 = 60;
+// Steps skipped with the left and right arrows when watching a replay
+const REPLAY_JUMP_STEPS: u64 = 300;
 
 
 fn main() {
+    // Watch a recorded game
+    let replay_log = argument_value("--replay").map(|path| match InputLog::load(Path::new(&path)) {
+        Ok(log) => log,
+        Err(error) => {
+            println!("{}", error);
+            process::exit(1);
+        },
+    });
+    // Record every game that's played, replacing the recording of the previous one
+    let record_path = argument_value("--record").map(PathBuf::from);
+    // How much of a band the area coverage line detector needs covered, e.g. `--line-coverage 80`
+    let line_coverage = match argument_value("--line-coverage").map(|percent| percent.parse::<f32>()) {
+        Some(Ok(percent)) if percent > 0.0 && percent <= 100.0 => percent / 100.0,
+        Some(_) => {
+            println!("--line-coverage takes a percentage between 0 and 100");
+            process::exit(1);
+        },
+        None => consts::DEFAULT_LINE_COVERAGE,
+    };
+
+    // Versus games against another instance, on this machine or over the network
+    let network = argument_value("--host").map(|address| (true, address))
+        .or(argument_value("--join").map(|address| (false, address)));
+
+    // Versus games are played side by side, in a window twice as wide, and co-op games in a wider well.
+    // Replays have their timeline below the well.
+    let versus = replay_log.is_none() && (env::args().any(|arg| arg == "--versus") || network.is_some());
+    let coop = env::args().any(|arg| arg == "--coop");
+    let columns = match replay_log {
+        Some(ref log) => log.mode.columns(),
+        None if coop => Mode::Coop.columns(),
+        None => consts::BLOCKS_PER_LINE,
+    };
+    let width = if versus { 2 * WINDOW_WIDTH } else { WINDOW_WIDTH * columns as u32 / consts::BLOCKS_PER_LINE as u32 };
+    let height = if replay_log.is_some() { WINDOW_HEIGHT + TIMELINE_HEIGHT } else { WINDOW_HEIGHT };
     let display = glium::glutin::WindowBuilder::new()
         .with_title("Glium Tetris")
-        .with_dimensions(600, 800)
-        .with_min_dimensions(600, 800)
-        .with_max_dimensions(600, 800)
+        .with_dimensions(width, height)
+        .with_min_dimensions(width, height)
+        .with_max_dimensions(width, height)
         .with_multisampling(4)
         .with_vsync()
         .build_glium().unwrap();
@@ -37,9 +81,57 @@ fn main() {
     show_loading_screen(&display, &mut target);
     target.finish().unwrap();
 
-    let props = GraphicsProperties::new(&display);
+    let mut props = GraphicsProperties::new(&display);
+    let mut audio = Audio::new(if env::args().any(|arg| arg == "--no-audio") { Output::Null } else { Output::device() });
+    let mut overlays = Overlays::new();
+    // Stream the games to viewers, see src/bin/viewer.rs
+    let mut spectators = argument_value("--spectate").and_then(|address| match SpectatorServer::bind(&address) {
+        Ok(server) => {
+            println!("Streaming to spectators on {}", address);
+            Some(server)
+        },
+        Err(error) => {
+            println!("Can't stream to spectators on {}: {}", address, error);
+            None
+        },
+    });
+    if let Some(log) = replay_log {
+        return run_replay(&display, &mut props, &mut audio, &mut overlays, Replay::new(log));
+    }
+    if let Some((host, address)) = network {
+        let handshake = if host {
+            println!("Waiting for the other player to connect to {}...", address);
+            Lockstep::start_host(&address)
+        } else {
+            Lockstep::start_join(&address)
+        };
+        return match wait_for_handshake(&display, handshake) {
+            Some(Ok(lockstep)) => run_network(&display, &mut props, &mut audio, &mut overlays, &mut spectators, lockstep),
+            Some(Err(error)) => {
+                println!("{}", error);
+                process::exit(1);
+            },
+            // The window was closed before the other player showed up
+            None => {},
+        };
+    }
+    if versus {
+        return run_versus(&display, &mut props, &mut audio, &mut overlays, &mut spectators);
+    }
+    if coop {
+        return run_coop(&display, &mut props, &mut audio, &mut overlays, &mut spectators, line_coverage, record_path);
+    }
 
     let mut game = Game::new();
+    game.set_line_coverage(line_coverage);
+    let mut records = Records::load();
+    let mut particles = [ParticleSystem::new()];
+    let mut bot = if env::args().any(|arg| arg == "--demo") { Some(Bot::new(Role::Demo)) } else { None };
+    let mut recorder = record_path.map(|path| Recorder::new(path, &game));
+    // Games in which the bot played don't count for the records, and neither do rewound games,
+    // unless that's explicitly allowed
+    let mut assisted = false;
+    let allow_rewinds = env::args().any(|arg| arg == "--allow-rewind-records");
     let mut last_update = Instant::now();
 
     'mainloop: loop {
@@ -50,37 +142,501 @@ fn main() {
             use glium::glutin::VirtualKeyCode::*;
             match event {
                 Closed | KeyboardInput(Pressed, _, Some(Escape)) => break 'mainloop,
-                KeyboardInput(Pressed, _, Some(Back)) => game.reset(),
-
-                KeyboardInput(Pressed, _, Some(keycode)) => if let Some(action) = Controls.resolve_press(keycode) {
+                // Any key ends the demo, and starts a real game
+                KeyboardInput(Pressed, _, Some(_)) if bot.as_ref().map(Bot::role) == Some(Role::Demo) => {
+                    bot = None;
+                    game.reset();
+                    restart_recording(&mut recorder, &game);
+                    records.clear_submission();
+                    audio.reset();
+                    assisted = false;
+                },
+                KeyboardInput(Pressed, _, Some(keycode)) => if let Some(command) = Controls::Single.resolve_command(keycode) {
+                    match command {
+                        Command::ToggleBot => bot = match bot {
+                            Some(_) => None,
+                            None => Some(Bot::new(Role::Player)),
+                        },
+                        _ => handle_command(command, &mut particles, &mut overlays, &mut audio),
+                    }
+                } else if let Some(action) = Controls::Single.resolve_press(keycode) {
+                    if bot.is_some() && action.is_piece_control() { continue; }
+                    match action {
+                        Action::GameReset | Action::SelectMode(_) => {
+                            records.clear_submission();
+                            audio.reset();
+                            assisted = bot.is_some();
+                        },
+                        _ => {},
+                    }
                     game.execute_action(action);
+                    match action {
+                        Action::GameReset | Action::SelectMode(_) => restart_recording(&mut recorder, &game),
+                        _ => if let Some(ref mut recorder) = recorder { recorder.record(0, action) },
+                    }
                 },
-                KeyboardInput(Released, _, Some(keycode)) => if let Some(action) = Controls.resolve_release(keycode) {
+                KeyboardInput(Released, _, Some(keycode)) => if let Some(action) = Controls::Single.resolve_release(keycode) {
+                    if bot.is_some() { continue; }
                     game.execute_action(action);
+                    if let Some(ref mut recorder) = recorder { recorder.record(0, action) }
                 },
 
                 _ => {},
             }
         }
 
+        // Let the bot play
+        if let Some(ref mut bot) = bot {
+            assisted = true;
+            for action in bot.update(&game) {
+                if let Some(ref mut recorder) = recorder { recorder.record(0, action) }
+                game.execute_action(action);
+            }
+        }
+        overlays.banner = bot.as_ref().map(|bot| bot.role().describe().to_owned());
+
         // Update the game logic
         game.update();
+        if let Some(ref mut recorder) = recorder { recorder.update() }
+        let counts_for_records = !assisted && (allow_rewinds || game.rewinds() == 0);
+        for event in game.drain_events() {
+            if let GameEvent::GameOver { outcome } = event {
+                if counts_for_records {
+                    records.submit(outcome);
+                }
+            }
+            particles[0].handle_event(&event);
+            audio.handle_event(&event);
+        }
+        particles[0].update(consts::TIMESTEP);
+        audio.update();
+        // The demo starts over when it's done
+        if bot.as_ref().map(Bot::role) == Some(Role::Demo) && (game.outcome().is_some() || game.topped_out()) {
+            game.reset();
+            restart_recording(&mut recorder, &game);
+            audio.reset();
+        }
+
+        if let Some(ref mut server) = spectators {
+            server.broadcast(game.ticks(), &[&game]);
+        }
 
         // Draw everything
         let mut target = display.draw();
         target.clear_color(0.0, 0.0, 0.0, 1.0);
-        game.draw(&display, &mut target, &props);
+        game.draw(&display, &mut target, &props, &records, &overlays);
+        particles[0].draw(&display, &mut target, &props);
         target.finish().unwrap();
 
 
         // TODO?
         // display.swap_buffers().unwrap();
-        // Wait for next loop
-        let now = Instant::now();
-        let delta = now - last_update;
-        if delta < Duration::from_millis(16) {
-            ::std::thread::sleep(Duration::from_millis(16) - delta);
+        wait_for_next_frame(&mut last_update);
+    }
+    stop_recording(&recorder);
+}
+
+fn run_versus(display: &GlutinFacade, props: &mut GraphicsProperties, audio: &mut Audio, overlays: &mut Overlays,
+              spectators: &mut Option<SpectatorServer>) {
+    let mut versus = Versus::new();
+    let records = Records::load();
+    let mut particles = [ParticleSystem::new(), ParticleSystem::new()];
+    let keysets = [Controls::Left, Controls::Right];
+    let mut last_update = Instant::now();
+
+    'mainloop: loop {
+        for event in display.poll_events() {
+            use glium::glutin::Event::*;
+            use glium::glutin::ElementState::*;
+            use glium::glutin::VirtualKeyCode::*;
+            match event {
+                Closed | KeyboardInput(Pressed, _, Some(Escape)) => break 'mainloop,
+                KeyboardInput(state, _, Some(keycode)) => {
+                    // Each player controls their own piece
+                    let mut handled = false;
+                    for (player, keyset) in keysets.iter().enumerate() {
+                        let action = match state {
+                            Pressed => keyset.resolve_press(keycode),
+                            Released => keyset.resolve_release(keycode),
+                        };
+                        if let Some(action) = action {
+                            versus.game_mut(player).execute_action(action);
+                            handled = true;
+                        }
+                    }
+                    if handled || state == Released { continue; }
+                    if let Some(command) = Controls::Single.resolve_command(keycode) {
+                        handle_command(command, &mut particles, overlays, audio);
+                    }
+                    // Only some of the other keys apply to versus games
+                    if let Some(Action::GameReset) = Controls::Single.resolve_press(keycode) {
+                        versus.reset();
+                        audio.reset();
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        for (player, event) in versus.update() {
+            particles[player].handle_event(&event);
+            audio.handle_event(&event);
+        }
+        for particles in particles.iter_mut() {
+            particles.update(consts::TIMESTEP);
+        }
+        audio.update();
+        if let Some(ref mut server) = *spectators {
+            server.broadcast(versus.game(0).ticks(), &[versus.game(0), versus.game(1)]);
+        }
+
+        let banners = [versus_banner(&versus, 0), versus_banner(&versus, 1)];
+        draw_versus(display, props, &versus, &records, &particles, overlays, &banners);
+
+        wait_for_next_frame(&mut last_update);
+    }
+}
+
+// Keep the window responsive until the connection to the other instance is set up. Returns None
+// if the window is closed first.
+fn wait_for_handshake(display: &GlutinFacade, handshake: Handshake) -> Option<Result<Lockstep, String>> {
+    let mut last_update = Instant::now();
+    loop {
+        for event in display.poll_events() {
+            use glium::glutin::Event::*;
+            use glium::glutin::ElementState::*;
+            use glium::glutin::VirtualKeyCode::*;
+            match event {
+                Closed | KeyboardInput(Pressed, _, Some(Escape)) => return None,
+                _ => {},
+            }
+        }
+        if let Some(result) = handshake.poll() {
+            return Some(result);
+        }
+
+        let mut target = display.draw();
+        show_loading_screen(display, &mut target);
+        target.finish().unwrap();
+
+        wait_for_next_frame(&mut last_update);
+    }
+}
+
+fn run_network(display: &GlutinFacade, props: &mut GraphicsProperties, audio: &mut Audio, overlays: &mut Overlays,
+               spectators: &mut Option<SpectatorServer>, mut lockstep: Lockstep) {
+    let records = Records::load();
+    let mut particles = [ParticleSystem::new(), ParticleSystem::new()];
+    let local = lockstep.local_player();
+    let mut reported = false;
+    let mut last_update = Instant::now();
+
+    'mainloop: loop {
+        for event in display.poll_events() {
+            use glium::glutin::Event::*;
+            use glium::glutin::ElementState::*;
+            use glium::glutin::VirtualKeyCode::*;
+            match event {
+                Closed | KeyboardInput(Pressed, _, Some(Escape)) => break 'mainloop,
+                // Pieces and rematches go through the other side, the rest only changes this window
+                KeyboardInput(Pressed, _, Some(keycode)) => if let Some(command) = Controls::Single.resolve_command(keycode) {
+                    handle_command(command, &mut particles, overlays, audio);
+                } else {
+                    match Controls::Single.resolve_press(keycode) {
+                        Some(Action::GameReset) => lockstep.queue(Action::GameReset),
+                        Some(action) if action.is_piece_control() => lockstep.queue(action),
+                        _ => {},
+                    }
+                },
+                KeyboardInput(Released, _, Some(keycode)) => if let Some(action) = Controls::Single.resolve_release(keycode) {
+                    lockstep.queue(action);
+                },
+                _ => {},
+            }
+        }
+
+        let decided = lockstep.versus().over();
+        for (player, event) in lockstep.update() {
+            particles[player].handle_event(&event);
+            audio.handle_event(&event);
+        }
+        // The music starts over with the rematch
+        if decided && !lockstep.versus().over() {
+            audio.reset();
+        }
+        for particles in particles.iter_mut() {
+            particles.update(consts::TIMESTEP);
+        }
+        audio.update();
+        if let Some(ref mut server) = *spectators {
+            server.broadcast(lockstep.tick(), &[lockstep.versus().game(0), lockstep.versus().game(1)]);
+        }
+
+        if !reported {
+            if let Some(desync) = lockstep.desync() {
+                println!("Out of sync with the other player since tick {} (checksum {:016x} here, {:016x} there)",
+                         desync.tick, desync.local, desync.remote);
+                reported = true;
+            }
+        }
+        let mut banners = [versus_banner(lockstep.versus(), 0), versus_banner(lockstep.versus(), 1)];
+        banners[local] = format!("{} (you)", banners[local]);
+        if let Some(error) = lockstep.error() {
+            banners[local] = format!("Connection lost: {}", error);
+        } else if let Some(desync) = lockstep.desync() {
+            banners[local] = format!("Out of sync since tick {}", desync.tick);
         }
-        last_update = now;
+        draw_versus(display, props, lockstep.versus(), &records, &particles, overlays, &banners);
+
+        wait_for_next_frame(&mut last_update);
+    }
+}
+
+// The top line of the HUD of a player in a versus game
+fn versus_banner(versus: &Versus, player: usize) -> String {
+    match versus.end() {
+        Some(RoundEnd::Winner(winner)) if winner == player => format!("Player {} wins! Backspace for a rematch", player + 1),
+        Some(RoundEnd::Winner(_)) => format!("Player {} loses", player + 1),
+        Some(RoundEnd::Draw) => format!("Player {}: draw, both wells topped out! Backspace for a rematch", player + 1),
+        None => format!("Player {}  Wins: {}  Garbage sent: {}", player + 1, versus.wins()[player], versus.sent(player)),
+    }
+}
+
+// Draw both games of a versus game side by side
+fn draw_versus(display: &GlutinFacade, props: &mut GraphicsProperties, versus: &Versus, records: &Records,
+               particles: &[ParticleSystem], overlays: &mut Overlays, banners: &[String]) {
+    let mut target = display.draw();
+    target.clear_color(0.0, 0.0, 0.0, 1.0);
+    for player in 0..2 {
+        props.set_viewport(Viewport::column(player, 2));
+        overlays.banner = Some(banners[player].clone());
+        versus.game(player).draw(display, &mut target, props, records, overlays);
+        particles[player].draw(display, &mut target, props);
+        draw_frame(display, &mut target, props);
+    }
+    props.set_viewport(Viewport::full());
+    target.finish().unwrap();
+}
+
+fn run_coop(display: &GlutinFacade, props: &mut GraphicsProperties, audio: &mut Audio, overlays: &mut Overlays,
+            spectators: &mut Option<SpectatorServer>, line_coverage: f32, record_path: Option<PathBuf>) {
+    let mut game = Game::new();
+    game.set_mode(Mode::Coop);
+    game.set_line_coverage(line_coverage);
+    let mut recorder = record_path.map(|path| Recorder::new(path, &game));
+    props.set_well_width(game.well_right() - consts::LEFT);
+    let records = Records::load();
+    let mut particles = [ParticleSystem::new()];
+    let keysets = [Controls::Left, Controls::Right];
+    overlays.banner = Some("Player 1: WASD + left Shift (blue)  Player 2: arrows + right Shift (orange)".to_owned());
+    let mut last_update = Instant::now();
+
+    'mainloop: loop {
+        for event in display.poll_events() {
+            use glium::glutin::Event::*;
+            use glium::glutin::ElementState::*;
+            use glium::glutin::VirtualKeyCode::*;
+            match event {
+                Closed | KeyboardInput(Pressed, _, Some(Escape)) => break 'mainloop,
+                KeyboardInput(state, _, Some(keycode)) => {
+                    // Each player controls their own piece
+                    let mut handled = false;
+                    for (player, keyset) in keysets.iter().enumerate() {
+                        let action = match state {
+                            Pressed => keyset.resolve_press(keycode),
+                            Released => keyset.resolve_release(keycode),
+                        };
+                        if let Some(action) = action {
+                            game.execute_player_action(player, action);
+                            if let Some(ref mut recorder) = recorder { recorder.record(player, action) }
+                            handled = true;
+                        }
+                    }
+                    if handled || state == Released { continue; }
+                    if let Some(command) = Controls::Single.resolve_command(keycode) {
+                        handle_command(command, &mut particles, overlays, audio);
+                    }
+                    // The mode can't be changed, and co-op games can't be rewound
+                    match Controls::Single.resolve_press(keycode) {
+                        Some(Action::GameReset) => {
+                            game.reset();
+                            restart_recording(&mut recorder, &game);
+                            audio.reset();
+                        },
+                        Some(action @ Action::ToggleLineDetector) | Some(action @ Action::ToggleSplitMode) => {
+                            game.execute_action(action);
+                            if let Some(ref mut recorder) = recorder { recorder.record(0, action) }
+                        },
+                        _ => {},
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        game.update();
+        if let Some(ref mut recorder) = recorder { recorder.update() }
+        for event in game.drain_events() {
+            particles[0].handle_event(&event);
+            audio.handle_event(&event);
+        }
+        particles[0].update(consts::TIMESTEP);
+        audio.update();
+        if let Some(ref mut server) = *spectators {
+            server.broadcast(game.ticks(), &[&game]);
+        }
+
+        let mut target = display.draw();
+        target.clear_color(0.0, 0.0, 0.0, 1.0);
+        game.draw(display, &mut target, props, &records, overlays);
+        particles[0].draw(display, &mut target, props);
+        target.finish().unwrap();
+
+        wait_for_next_frame(&mut last_update);
+    }
+    stop_recording(&recorder);
+}
+
+fn run_replay(display: &GlutinFacade, props: &mut GraphicsProperties, audio: &mut Audio, overlays: &mut Overlays,
+              mut replay: Replay) {
+    let records = Records::load();
+    let mut particles = [ParticleSystem::new()];
+    props.set_well_width(replay.game().well_right() - consts::LEFT);
+    // The well is drawn above the timeline
+    let timeline_height = TIMELINE_HEIGHT as f32 / (WINDOW_HEIGHT + TIMELINE_HEIGHT) as f32;
+    let well_viewport = Viewport { left: 0.0, bottom: timeline_height, width: 1.0, height: 1.0 - timeline_height };
+    let timeline_viewport = Viewport { left: 0.0, bottom: 0.0, width: 1.0, height: timeline_height };
+    let mut cursor = (0, 0);
+    let mut scrubbing = false;
+    let mut last_update = Instant::now();
+
+    'mainloop: loop {
+        let (window_width, window_height) = display.get_window()
+            .and_then(|window| window.get_inner_size_pixels())
+            .unwrap_or((WINDOW_WIDTH, WINDOW_HEIGHT + TIMELINE_HEIGHT));
+        // The step under the mouse cursor, if it's on the timeline
+        let step_at = |props: &GraphicsProperties, (x, y): (i32, i32), replay: &Replay| {
+            if (y as f32) < window_height as f32 * (1.0 - timeline_height) { return None; }
+            Some(timeline_step(props, x as f32 / window_width as f32, replay.steps()))
+        };
+
+        for event in display.poll_events() {
+            use glium::glutin::Event::*;
+            use glium::glutin::ElementState::*;
+            use glium::glutin::MouseButton;
+            use glium::glutin::VirtualKeyCode::*;
+            match event {
+                Closed | KeyboardInput(Pressed, _, Some(Escape)) => break 'mainloop,
+                KeyboardInput(Pressed, _, Some(Space)) => replay.toggle_pause(),
+                KeyboardInput(Pressed, _, Some(Up)) => replay.faster(),
+                KeyboardInput(Pressed, _, Some(Down)) => replay.slower(),
+                KeyboardInput(Pressed, _, Some(keycode @ Left)) | KeyboardInput(Pressed, _, Some(keycode @ Right)) |
+                KeyboardInput(Pressed, _, Some(keycode @ Comma)) | KeyboardInput(Pressed, _, Some(keycode @ Period)) => {
+                    // Jump a few seconds, or a single step
+                    let step = match keycode {
+                        Left => replay.step().saturating_sub(REPLAY_JUMP_STEPS),
+                        Right => replay.step() + REPLAY_JUMP_STEPS,
+                        Comma => replay.step().saturating_sub(1),
+                        _ => replay.step() + 1,
+                    };
+                    replay.seek(step);
+                },
+                KeyboardInput(Pressed, _, Some(Home)) => replay.seek(0),
+                KeyboardInput(Pressed, _, Some(End)) => {
+                    let end = replay.steps();
+                    replay.seek(end);
+                },
+                KeyboardInput(Pressed, _, Some(PageUp)) => if let Some(step) = replay.previous_clear() {
+                    replay.seek(step);
+                },
+                KeyboardInput(Pressed, _, Some(PageDown)) => if let Some(step) = replay.next_clear() {
+                    replay.seek(step);
+                },
+                KeyboardInput(Pressed, _, Some(keycode)) => if let Some(command) = Controls::Single.resolve_command(keycode) {
+                    handle_command(command, &mut particles, overlays, audio);
+                },
+                // Clicking or dragging on the timeline jumps there
+                MouseInput(Pressed, MouseButton::Left) => if let Some(step) = step_at(props, cursor, &replay) {
+                    replay.seek(step);
+                    scrubbing = true;
+                },
+                MouseInput(Released, MouseButton::Left) => scrubbing = false,
+                MouseMoved(x, y) => {
+                    cursor = (x, y);
+                    if scrubbing {
+                        if let Some(step) = step_at(props, cursor, &replay) {
+                            replay.seek(step);
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        for event in replay.update() {
+            particles[0].handle_event(&event);
+            audio.handle_event(&event);
+        }
+        particles[0].update(consts::TIMESTEP);
+        audio.update();
+
+        let state = if replay.finished() { "Finished" } else if replay.paused() { "Paused" } else { "Playing" };
+        overlays.banner = Some(format!("Replay: {} at {}x, step {} of {}", state, replay.speed(), replay.step(), replay.steps()));
+        let mut target = display.draw();
+        target.clear_color(0.0, 0.0, 0.0, 1.0);
+        props.set_viewport(well_viewport);
+        replay.game().draw(display, &mut target, props, &records, overlays);
+        particles[0].draw(display, &mut target, props);
+        props.set_viewport(timeline_viewport);
+        draw_timeline(display, &mut target, props, replay.step(), replay.steps(), replay.clears());
+        target.finish().unwrap();
+
+        wait_for_next_frame(&mut last_update);
+    }
+}
+
+// Save the recording of the game that just ended, and start recording the next one
+fn restart_recording(recorder: &mut Option<Recorder>, game: &Game) {
+    if let Some(ref mut recorder) = *recorder {
+        if let Err(error) = recorder.restart(game) {
+            println!("Can't save the replay: {}", error);
+        }
+    }
+}
+
+fn stop_recording(recorder: &Option<Recorder>) {
+    if let Some(ref recorder) = *recorder {
+        if let Err(error) = recorder.save() {
+            println!("Can't save the replay: {}", error);
+        }
+    }
+}
+
+// Handle the keys that only change how the game is presented. The bot is only available in single player games.
+fn handle_command(command: Command, particles: &mut [ParticleSystem], overlays: &mut Overlays, audio: &mut Audio) {
+    match command {
+        Command::ToggleParticles => for particles in particles.iter_mut() { particles.toggle() },
+        Command::ToggleGhost => overlays.ghost = !overlays.ghost,
+        Command::TogglePrediction => overlays.prediction = !overlays.prediction,
+        Command::ToggleDebug => overlays.debug = !overlays.debug,
+        Command::ToggleMute => audio.toggle_mute(),
+        Command::VolumeUp => audio.change_volume(consts::VOLUME_STEP),
+        Command::VolumeDown => audio.change_volume(-consts::VOLUME_STEP),
+        Command::ToggleBot => {},
+    }
+}
+
+// The value following a command line argument, e.g. the address in `--host 0.0.0.0:7777`
+fn argument_value(name: &str) -> Option<String> {
+    env::args().skip_while(|arg| arg != name).nth(1)
+}
+
+// Sleep until it's time for the next frame
+fn wait_for_next_frame(last_update: &mut Instant) {
+    let now = Instant::now();
+    let delta = now - *last_update;
+    if delta < Duration::from_millis(consts::TIMESTEP_MS) {
+        ::std::thread::sleep(Duration::from_millis(consts::TIMESTEP_MS) - delta);
     }
+    *last_update = now;
 }
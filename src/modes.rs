@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use consts::*;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mode {
+    // Endless play
+    Marathon,
+    // Clear a number of lines as fast as possible
+    Sprint,
+    // Score as much as possible within a time limit
+    Ultra,
+    // Stay alive as long as possible while garbage rises from the bottom
+    Survival,
+    // Two players, who send each other garbage rows by clearing multiple lines at once
+    Versus,
+    // Two players in one wide well, with a shared score
+    Coop,
+}
+
+impl Mode {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Mode::Marathon => "Marathon",
+            Mode::Sprint   => "Sprint",
+            Mode::Ultra    => "Ultra",
+            Mode::Survival => "Survival",
+            Mode::Versus   => "Versus",
+            Mode::Coop     => "Co-op",
+        }
+    }
+
+    // Time between a reset and the moment the player can start playing
+    pub fn countdown(&self) -> Duration {
+        match *self {
+            Mode::Marathon => Duration::from_millis(0),
+            Mode::Sprint | Mode::Ultra | Mode::Survival |
+            Mode::Versus | Mode::Coop => Duration::from_millis(COUNTDOWN_MS),
+        }
+    }
+
+    // True if the game ends when the well tops out
+    pub fn tops_out(&self) -> bool {
+        *self == Mode::Survival || *self == Mode::Versus || *self == Mode::Coop
+    }
+
+    // Number of players sharing the well
+    pub fn players(&self) -> usize {
+        match *self {
+            Mode::Coop => 2,
+            _ => 1,
+        }
+    }
+
+    // Width of the well, in blocks
+    pub fn columns(&self) -> usize {
+        match *self {
+            Mode::Coop => COOP_BLOCKS_PER_LINE,
+            _ => BLOCKS_PER_LINE,
+        }
+    }
+
+    pub fn time_limit(&self) -> Option<Duration> {
+        match *self {
+            Mode::Ultra => Some(Duration::from_millis(ULTRA_TIME_MS)),
+            _ => None,
+        }
+    }
+}
+
+// The result of a game that has ended.
+#[derive(Copy, Clone, Debug)]
+pub enum Outcome {
+    SprintTime(Duration),
+    UltraScore(usize),
+    SurvivalTime(Duration),
+    // Lost a versus game, or ended a co-op game, after this long
+    ToppedOut(Duration),
+}
+
+// Format a duration as m:ss.mmm
+pub fn format_time(duration: Duration) -> String {
+    let millis = duration.as_secs() * 1000 + duration.subsec_nanos() as u64 / 1_000_000;
+    format!("{}:{:02}.{:03}", millis / 60_000, (millis / 1000) % 60, millis % 1000)
+}
+
+// Format the difference between two durations as +s.mmm or -s.mmm
+pub fn format_time_delta(time: Duration, reference: Duration) -> String {
+    if time >= reference {
+        format!("+{}", format_seconds(time - reference))
+    }
+    else {
+        format!("-{}", format_seconds(reference - time))
+    }
+}
+
+fn format_seconds(duration: Duration) -> String {
+    format!("{}.{:03}", duration.as_secs(), duration.subsec_nanos() / 1_000_000)
+}
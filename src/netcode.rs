@@ -0,0 +1,608 @@
+// Versus games between two instances of the game over TCP, in deterministic lockstep.
+//
+// Both instances simulate both games. Every tick, each side sends the actions of its own player a
+// few ticks ahead of time, and a tick is only simulated once the actions of both players for it
+// have arrived. Every now and then, both sides send a checksum of the bodies in the games, so that
+// games that went out of sync are noticed. Messages are lines of text:
+//
+//   HELLO <version> <seed>       Sent by both sides after connecting. The host picks the seed.
+//   INPUT <tick> <action>...     The actions of the sender's player at the start of a tick.
+//   CHECK <tick> <checksum>      Checksum of the games after a tick, as 16 hex digits.
+//
+// Actions are `cw`, `ccw`, `rotate-stop`, `left`, `right`, `move-stop`, `spawn` and `reset`.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use na::Rotation;
+use rand::{self, Rng};
+
+use controls::Action;
+use events::GameEvent;
+use game::Game;
+use versus::Versus;
+
+const PROTOCOL_VERSION: u64 = 1;
+// Local actions are sent this many ticks before they're simulated, to hide the latency
+const INPUT_DELAY_TICKS: u64 = 4;
+const CHECKSUM_INTERVAL_TICKS: u64 = 30;
+// Ticks simulated in one update at most, when catching up after a hiccup
+const MAX_TICKS_PER_UPDATE: usize = 4;
+// The two sides stay within a few input delays of each other, so messages about ticks further than
+// this from the current one come from a broken peer, and are rejected
+const REMOTE_TICK_WINDOW: u64 = 120;
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Message {
+    Hello { version: u64, seed: u32 },
+    Input { tick: u64, actions: Vec<Action> },
+    Check { tick: u64, checksum: u64 },
+}
+
+impl Message {
+    pub fn encode(&self) -> String {
+        match *self {
+            Message::Hello { version, seed } => format!("HELLO {} {}", version, seed),
+            Message::Input { tick, ref actions } => {
+                let mut line = format!("INPUT {}", tick);
+                for action in actions {
+                    line.push(' ');
+                    line.push_str(action_name(action).expect("only piece controls and resets are sent"));
+                }
+                line
+            },
+            Message::Check { tick, checksum } => format!("CHECK {} {:016x}", tick, checksum),
+        }
+    }
+
+    pub fn decode(line: &str) -> Result<Message, String> {
+        let words: Vec<_> = line.split_whitespace().collect();
+        let malformed = || format!("malformed message: {:?}", line);
+        let number = |i: usize| words.get(i).and_then(|word| word.parse::<u64>().ok()).ok_or_else(&malformed);
+        match words.first() {
+            Some(&"HELLO") if words.len() == 3 => Ok(Message::Hello {
+                version: try!(number(1)),
+                seed: try!(words[2].parse::<u32>().map_err(|_| malformed())),
+            }),
+            Some(&"INPUT") if words.len() >= 2 => {
+                let mut actions = vec![];
+                for word in words.iter().skip(2) {
+                    actions.push(try!(action_by_name(word).ok_or_else(&malformed)));
+                }
+                Ok(Message::Input { tick: try!(number(1)), actions: actions })
+            },
+            Some(&"CHECK") if words.len() == 3 => Ok(Message::Check {
+                tick: try!(number(1)),
+                checksum: try!(u64::from_str_radix(words[2], 16).map_err(|_| malformed())),
+            }),
+            _ => Err(malformed()),
+        }
+    }
+}
+
+fn action_name(action: &Action) -> Option<&'static str> {
+    Some(match *action {
+        Action::RotateCW   => "cw",
+        Action::RotateCCW  => "ccw",
+        Action::RotateStop => "rotate-stop",
+        Action::MoveLeft   => "left",
+        Action::MoveRight  => "right",
+        Action::MoveStop   => "move-stop",
+        Action::TrySpawn   => "spawn",
+        Action::GameReset  => "reset",
+        _ => return None,
+    })
+}
+
+fn action_by_name(name: &str) -> Option<Action> {
+    Some(match name {
+        "cw"          => Action::RotateCW,
+        "ccw"         => Action::RotateCCW,
+        "rotate-stop" => Action::RotateStop,
+        "left"        => Action::MoveLeft,
+        "right"       => Action::MoveRight,
+        "move-stop"   => Action::MoveStop,
+        "spawn"       => Action::TrySpawn,
+        "reset"       => Action::GameReset,
+        _ => return None,
+    })
+}
+
+// The connection to the other instance. Incoming messages are read on a separate thread, so that
+// the game never waits for the network.
+pub struct Peer {
+    stream: TcpStream,
+    incoming: Receiver<Result<Message, String>>,
+}
+
+impl Peer {
+    // Wait for the other instance to connect to the given address, e.g. "0.0.0.0:7777"
+    pub fn host(address: &str) -> io::Result<Peer> {
+        let listener = try!(TcpListener::bind(address));
+        let (stream, _) = try!(listener.accept());
+        Peer::new(stream)
+    }
+
+    pub fn join(address: &str) -> io::Result<Peer> {
+        Peer::new(try!(TcpStream::connect(address)))
+    }
+
+    fn new(stream: TcpStream) -> io::Result<Peer> {
+        // Messages are small, and should go out right away
+        try!(stream.set_nodelay(true));
+        let reader = BufReader::new(try!(stream.try_clone()));
+        let (sender, incoming) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                let message = line.map_err(|error| format!("connection error: {}", error))
+                    .and_then(|line| Message::decode(&line));
+                let failed = message.is_err();
+                if sender.send(message).is_err() || failed { return; }
+            }
+            let _ = sender.send(Err("the other player disconnected".to_owned()));
+        });
+        Ok(Peer { stream: stream, incoming: incoming })
+    }
+
+    pub fn send(&mut self, message: &Message) -> Result<(), String> {
+        let line = format!("{}\n", message.encode());
+        self.stream.write_all(line.as_bytes()).map_err(|error| format!("connection error: {}", error))
+    }
+
+    // The next message that has arrived, if any
+    pub fn poll(&self) -> Option<Result<Message, String>> {
+        match self.incoming.try_recv() {
+            Ok(message) => Some(message),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err("the connection was closed".to_owned())),
+        }
+    }
+
+    // Wait for the next message
+    fn wait(&self) -> Result<Message, String> {
+        self.incoming.recv().unwrap_or_else(|_| Err("the connection was closed".to_owned()))
+    }
+}
+
+// The checksums of a tick that differed between the two instances
+#[derive(Copy, Clone, Debug)]
+pub struct Desync {
+    pub tick: u64,
+    pub local: u64,
+    pub remote: u64,
+}
+
+// A versus game against another instance
+pub struct Lockstep {
+    peer: Peer,
+    versus: Versus,
+    // The player controlled from this instance: 0 on the host, 1 on the other side
+    local: usize,
+    // The next tick to simulate, and the next tick to send the local actions for
+    tick: u64,
+    next_input: u64,
+    // Local actions that haven't been sent yet
+    pending: Vec<Action>,
+    // The actions of each player by tick, until the tick is simulated
+    inputs: [BTreeMap<u64, Vec<Action>>; 2],
+    // Checksums by tick, until the other side's checksum for the same tick is known
+    local_checksums: BTreeMap<u64, u64>,
+    remote_checksums: BTreeMap<u64, u64>,
+    desync: Option<Desync>,
+    error: Option<String>,
+}
+
+impl Lockstep {
+    // Wait for the other instance to connect, and start a game with a random seed
+    pub fn host(address: &str) -> Result<Self, String> {
+        Lockstep::start_host(address).wait()
+    }
+
+    // Connect to a hosting instance, and start a game with its seed
+    pub fn join(address: &str) -> Result<Self, String> {
+        Lockstep::start_join(address).wait()
+    }
+
+    // Like `host`, but the waiting happens on a separate thread
+    pub fn start_host(address: &str) -> Handshake {
+        Handshake::start(0, address, handshake_host)
+    }
+
+    // Like `join`, but the waiting happens on a separate thread
+    pub fn start_join(address: &str) -> Handshake {
+        Handshake::start(1, address, handshake_join)
+    }
+
+    fn new(peer: Peer, local: usize, seed: u32) -> Self {
+        let mut versus = Versus::new();
+        versus.reseed(seed);
+        // Nobody does anything until the first actions arrive
+        let mut inputs = [BTreeMap::new(), BTreeMap::new()];
+        for tick in 0..INPUT_DELAY_TICKS {
+            for inputs in inputs.iter_mut() {
+                inputs.insert(tick, vec![]);
+            }
+        }
+        Lockstep {
+            peer: peer,
+            versus: versus,
+            local: local,
+            tick: 0,
+            next_input: INPUT_DELAY_TICKS,
+            pending: vec![],
+            inputs: inputs,
+            local_checksums: BTreeMap::new(),
+            remote_checksums: BTreeMap::new(),
+            desync: None,
+            error: None,
+        }
+    }
+
+    pub fn versus(&self) -> &Versus { &self.versus }
+    pub fn local_player(&self) -> usize { self.local }
+    pub fn tick(&self) -> u64 { self.tick }
+
+    // The first tick after which the games were found to be out of sync
+    pub fn desync(&self) -> Option<Desync> { self.desync }
+
+    // Why the connection was lost, if it was
+    pub fn error(&self) -> Option<&str> { self.error.as_ref().map(|error| &error[..]) }
+
+    // Queue an action of the local player. Only piece controls are sent, and rematches once
+    // the round is over.
+    pub fn queue(&mut self, action: Action) {
+        let rematch = action == Action::GameReset && self.versus.over();
+        if action.is_piece_control() || rematch {
+            self.pending.push(action);
+        }
+    }
+
+    // Exchange actions with the other side, and simulate the ticks for which all actions are
+    // known. Returns the events of both games, together with the player they're from.
+    pub fn update(&mut self) -> Vec<(usize, GameEvent)> {
+        if self.error.is_none() {
+            self.exchange();
+        }
+
+        // Everything that arrived before the connection broke is still simulated
+        let mut events = vec![];
+        for _ in 0..MAX_TICKS_PER_UPDATE {
+            if !self.inputs.iter().all(|inputs| inputs.contains_key(&self.tick)) { break; }
+            for player in 0..2 {
+                for action in self.inputs[player].remove(&self.tick).unwrap() {
+                    self.apply(player, action);
+                }
+            }
+            events.extend(self.versus.update());
+
+            if self.tick % CHECKSUM_INTERVAL_TICKS == 0 {
+                let checksum = pose_checksum(&[self.versus.game(0), self.versus.game(1)]);
+                self.local_checksums.insert(self.tick, checksum);
+                if self.error.is_none() {
+                    if let Err(error) = self.peer.send(&Message::Check { tick: self.tick, checksum: checksum }) {
+                        self.error = Some(error);
+                    }
+                }
+            }
+            self.tick += 1;
+        }
+        self.compare_checksums();
+        events
+    }
+
+    // Send the local actions for every tick up to the input delay ahead, so that the other side
+    // can catch up, and take in everything that has arrived
+    fn exchange(&mut self) {
+        while self.next_input <= self.tick + INPUT_DELAY_TICKS {
+            // The actions queued since the last update go into the first tick sent
+            let actions = mem::replace(&mut self.pending, vec![]);
+            let message = Message::Input { tick: self.next_input, actions: actions.clone() };
+            if let Err(error) = self.peer.send(&message) {
+                self.error = Some(error);
+                return;
+            }
+            self.inputs[self.local].insert(self.next_input, actions);
+            self.next_input += 1;
+        }
+
+        while let Some(message) = self.peer.poll() {
+            match message {
+                Ok(Message::Input { tick, actions }) => {
+                    let remote = &mut self.inputs[1 - self.local];
+                    if tick < self.tick || tick > self.tick + REMOTE_TICK_WINDOW || remote.contains_key(&tick) {
+                        self.error = Some(format!("the other player sent actions for tick {} at tick {}", tick, self.tick));
+                        return;
+                    }
+                    remote.insert(tick, actions);
+                },
+                Ok(Message::Check { tick, checksum }) => {
+                    if tick < self.tick.saturating_sub(REMOTE_TICK_WINDOW) || tick > self.tick + REMOTE_TICK_WINDOW {
+                        self.error = Some(format!("the other player sent a checksum for tick {} at tick {}", tick, self.tick));
+                        return;
+                    }
+                    self.remote_checksums.insert(tick, checksum);
+                },
+                Ok(Message::Hello { .. }) => {
+                    self.error = Some("unexpected handshake".to_owned());
+                    return;
+                },
+                Err(error) => {
+                    self.error = Some(error);
+                    return;
+                },
+            }
+        }
+    }
+
+    fn apply(&mut self, player: usize, action: Action) {
+        match action {
+            // Both sides derive the seed of the rematch from the current one
+            Action::GameReset => {
+                let seed = self.versus.seed().wrapping_mul(1664525).wrapping_add(1013904223);
+                self.versus.reseed(seed);
+            },
+            action => self.versus.game_mut(player).execute_action(action),
+        }
+    }
+
+    fn compare_checksums(&mut self) {
+        let ticks: Vec<u64> = self.local_checksums.keys()
+            .filter(|tick| self.remote_checksums.contains_key(*tick))
+            .cloned()
+            .collect();
+        for tick in ticks {
+            let local = self.local_checksums.remove(&tick).unwrap();
+            let remote = self.remote_checksums.remove(&tick).unwrap();
+            if local != remote && self.desync.is_none() {
+                self.desync = Some(Desync { tick: tick, local: local, remote: remote });
+            }
+        }
+        // The other side's checksums for older ticks would be rejected, so stop waiting for them
+        let oldest = self.tick.saturating_sub(REMOTE_TICK_WINDOW);
+        self.local_checksums = self.local_checksums.split_off(&oldest);
+        self.remote_checksums = self.remote_checksums.split_off(&oldest);
+    }
+}
+
+// A connection to the other instance that's still being set up. The connection and the handshake
+// block, so they happen on a separate thread, and the window can stay responsive in the meantime.
+pub struct Handshake {
+    local: usize,
+    result: Receiver<Result<(Peer, u32), String>>,
+}
+
+impl Handshake {
+    fn start(local: usize, address: &str, handshake: fn(&str) -> Result<(Peer, u32), String>) -> Self {
+        let address = address.to_owned();
+        let (sender, result) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(handshake(&address));
+        });
+        Handshake { local: local, result: result }
+    }
+
+    // The game, once the other side has connected and answered
+    pub fn poll(&self) -> Option<Result<Lockstep, String>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(self.finish(result)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err("the handshake failed".to_owned())),
+        }
+    }
+
+    // Wait until the other side has connected and answered
+    pub fn wait(self) -> Result<Lockstep, String> {
+        let result = self.result.recv().unwrap_or_else(|_| Err("the handshake failed".to_owned()));
+        self.finish(result)
+    }
+
+    fn finish(&self, result: Result<(Peer, u32), String>) -> Result<Lockstep, String> {
+        let (peer, seed) = try!(result);
+        Ok(Lockstep::new(peer, self.local, seed))
+    }
+}
+
+fn handshake_host(address: &str) -> Result<(Peer, u32), String> {
+    let mut peer = try!(Peer::host(address).map_err(|error| format!("can't host on {}: {}", address, error)));
+    let seed = rand::thread_rng().gen();
+    try!(peer.send(&Message::Hello { version: PROTOCOL_VERSION, seed: seed }));
+    try!(expect_hello(&peer));
+    Ok((peer, seed))
+}
+
+fn handshake_join(address: &str) -> Result<(Peer, u32), String> {
+    let mut peer = try!(Peer::join(address).map_err(|error| format!("can't connect to {}: {}", address, error)));
+    let seed = try!(expect_hello(&peer));
+    try!(peer.send(&Message::Hello { version: PROTOCOL_VERSION, seed: seed }));
+    Ok((peer, seed))
+}
+
+// Wait for the handshake of the other side, and check that it speaks the same protocol.
+// Returns the seed it sent.
+fn expect_hello(peer: &Peer) -> Result<u32, String> {
+    match try!(peer.wait()) {
+        Message::Hello { version, seed } if version == PROTOCOL_VERSION => Ok(seed),
+        Message::Hello { version, .. } => Err(format!("the other player uses protocol version {}, not {}", version, PROTOCOL_VERSION)),
+        message => Err(format!("expected a handshake, got {:?}", message)),
+    }
+}
+
+// FNV-1a hash of the exact poses and velocities of every body in the games
+pub fn pose_checksum(games: &[&Game]) -> u64 {
+    let mut hash = FNV_OFFSET;
+    {
+        let mut add = |value: f32| {
+            let bits = value.to_bits();
+            for byte in 0..4 {
+                hash = (hash ^ ((bits >> (8 * byte)) & 0xff) as u64).wrapping_mul(FNV_PRIME);
+            }
+        };
+        for game in games {
+            for tetr in game.tetrominos() {
+                let rb = tetr.rbh.borrow();
+                let iso = rb.position();
+                add(iso.translation.x);
+                add(iso.translation.y);
+                add(iso.rotation().x);
+                add(rb.lin_vel().x);
+                add(rb.lin_vel().y);
+                add(rb.ang_vel().x);
+            }
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    use rand::Rng;
+
+    use controls::Action;
+    use game::tests::test_rng;
+    use versus::Versus;
+    use super::{pose_checksum, Lockstep, Message, Peer, CHECKSUM_INTERVAL_TICKS, INPUT_DELAY_TICKS,
+                MAX_TICKS_PER_UPDATE, PROTOCOL_VERSION, REMOTE_TICK_WINDOW};
+
+    const ACTIONS: [Action; 7] = [
+        Action::RotateCW, Action::RotateCCW, Action::RotateStop,
+        Action::MoveLeft, Action::MoveRight, Action::MoveStop,
+        Action::TrySpawn,
+    ];
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = vec![
+            Message::Hello { version: PROTOCOL_VERSION, seed: 0 },
+            Message::Hello { version: PROTOCOL_VERSION, seed: u32::max_value() },
+            Message::Input { tick: 0, actions: vec![] },
+            Message::Input { tick: 12345, actions: ACTIONS.iter().cloned().chain(Some(Action::GameReset)).collect() },
+            Message::Check { tick: 30, checksum: 0 },
+            Message::Check { tick: 60, checksum: u64::max_value() },
+        ];
+        for message in messages {
+            assert_eq!(Message::decode(&message.encode()), Ok(message.clone()), "{}", message.encode());
+        }
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected() {
+        let lines = [
+            "",
+            "BYE",
+            "hello 1 2",
+            "HELLO 1",
+            "HELLO 1 2 3",
+            "HELLO one 2",
+            "HELLO 1 -2",
+            "HELLO 1 4294967296",
+            "INPUT",
+            "INPUT x cw",
+            "INPUT 5 jump",
+            "INPUT 5 cw rotate",
+            "CHECK 5",
+            "CHECK 5 0123456789abcdef 1",
+            "CHECK 5 xyz",
+            "CHECK 5 10000000000000000",
+        ];
+        for line in lines.iter() {
+            assert!(Message::decode(line).is_err(), "{:?} was accepted", line);
+        }
+    }
+
+    // Both sides of a network game run the same games with the same actions; they have to
+    // stay identical to the bit
+    #[test]
+    fn same_seed_and_actions_stay_in_lockstep() {
+        let mut rng = test_rng();
+        let mut sides = [Versus::new(), Versus::new()];
+        for side in sides.iter_mut() {
+            side.reseed(1234);
+        }
+        for tick in 0..1500 {
+            for player in 0..2 {
+                if rng.gen_weighted_bool(10) {
+                    let action = *rng.choose(&ACTIONS).unwrap();
+                    for side in sides.iter_mut() {
+                        side.game_mut(player).execute_action(action);
+                    }
+                }
+            }
+            let events: Vec<_> = sides.iter_mut().map(|side| side.update().len()).collect();
+            assert_eq!(events[0], events[1], "different events at tick {}", tick);
+            let checksums: Vec<_> = sides.iter().map(|side| pose_checksum(&[side.game(0), side.game(1)])).collect();
+            assert_eq!(checksums[0], checksums[1], "out of sync at tick {}", tick);
+        }
+    }
+
+    // Both ends of a connection over localhost
+    fn connection() -> (Peer, Peer) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (host, _) = listener.accept().unwrap();
+        (Peer::new(host).unwrap(), Peer::new(client).unwrap())
+    }
+
+    #[test]
+    fn a_side_that_falls_behind_catches_up() {
+        let (host, client) = connection();
+        let (mut host, mut client) = (Lockstep::new(host, 0, 7), Lockstep::new(client, 1, 7));
+        // The client stalls, so the host runs out of the client's actions
+        for _ in 0..20 {
+            host.update();
+        }
+        assert_eq!(host.tick(), INPUT_DELAY_TICKS);
+        thread::sleep(Duration::from_millis(100));
+
+        // The client gets to the host's tick, and then as far as the host's actions go
+        client.update();
+        assert_eq!(client.tick(), INPUT_DELAY_TICKS);
+        client.update();
+        assert_eq!(client.tick(), INPUT_DELAY_TICKS + MAX_TICKS_PER_UPDATE as u64);
+        assert!(host.error().is_none() && client.error().is_none());
+    }
+
+    #[test]
+    fn messages_for_far_away_ticks_are_rejected() {
+        let messages = vec![Message::Input { tick: 1000000, actions: vec![] },
+                            Message::Check { tick: 1000000, checksum: 0 }];
+        for message in messages {
+            let (host, mut client) = connection();
+            let mut host = Lockstep::new(host, 0, 7);
+            client.send(&message).unwrap();
+            for _ in 0..100 {
+                if host.error().is_some() { break; }
+                host.update();
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert!(host.error().is_some(), "{:?} was accepted", message);
+            assert!(host.inputs[1].len() as u64 <= INPUT_DELAY_TICKS && host.remote_checksums.is_empty());
+        }
+    }
+
+    #[test]
+    fn checksums_the_other_side_never_sends_are_dropped() {
+        let (host, mut client) = connection();
+        let mut host = Lockstep::new(host, 0, 7);
+        // The client sends its actions, but never a checksum
+        let mut next = INPUT_DELAY_TICKS;
+        while host.tick() < 1000 {
+            while next <= host.tick() + INPUT_DELAY_TICKS {
+                client.send(&Message::Input { tick: next, actions: vec![] }).unwrap();
+                next += 1;
+            }
+            host.update();
+            assert!(host.error().is_none());
+        }
+        assert!(host.local_checksums.len() as u64 <= REMOTE_TICK_WINDOW / CHECKSUM_INTERVAL_TICKS + 1);
+    }
+}
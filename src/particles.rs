@@ -0,0 +1,125 @@
+use na::{Point2, Vector2};
+
+use glium::Surface;
+use glium::backend::Facade;
+use glium::index::PrimitiveType;
+
+use rand::{self, Rng, XorShiftRng};
+use rand::distributions::{IndependentSample, Range};
+
+use consts::*;
+use events::GameEvent;
+use graphics::{draw_mesh, GraphicsProperties, Vertex};
+
+const DEBRIS_PER_BLOCK: usize = 12;
+const DEBRIS_SPEED: f32 = 4.0;
+const DEBRIS_LIFE: f32 = 0.9;
+const DEBRIS_SIZE: f32 = 0.12;
+const SPARKS_PER_ESTIMATED_IMPULSE: f32 = 6.0;
+const MAX_SPARKS: usize = 24;
+const SPARK_SPEED: f32 = 6.0;
+const SPARK_LIFE: f32 = 0.35;
+const SPARK_SIZE: f32 = 0.05;
+const SPARK_COLOR: [f32; 3] = [1.0, 0.85, 0.4];
+
+struct Particle {
+    position: Point2<f32>,
+    velocity: Vector2<f32>,
+    color: [f32; 3],
+    size: f32,
+    life: f32,
+    max_life: f32,
+}
+
+// Purely visual particles, simulated outside of the physics world.
+pub struct ParticleSystem {
+    particles: Vec<Particle>,
+    enabled: bool,
+    rng: XorShiftRng,
+}
+
+impl ParticleSystem {
+    pub fn new() -> Self {
+        ParticleSystem {
+            particles: vec![],
+            enabled: true,
+            rng: rand::thread_rng().gen(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool { self.enabled }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.particles.clear();
+    }
+
+    pub fn handle_event(&mut self, event: &GameEvent) {
+        if !self.enabled { return; }
+        match *event {
+            // Removed blocks burst into debris
+            GameEvent::BlockRemoved { position, color } => {
+                let offset = Range::new(-BLOCK_SIZE / 2.0, BLOCK_SIZE / 2.0);
+                let speed = Range::new(-DEBRIS_SPEED, DEBRIS_SPEED);
+                for _ in 0..DEBRIS_PER_BLOCK {
+                    let particle = Particle {
+                        position: position + Vector2::new(offset.ind_sample(&mut self.rng), offset.ind_sample(&mut self.rng)),
+                        velocity: Vector2::new(speed.ind_sample(&mut self.rng), speed.ind_sample(&mut self.rng).abs()),
+                        color: color,
+                        size: DEBRIS_SIZE,
+                        life: DEBRIS_LIFE,
+                        max_life: DEBRIS_LIFE,
+                    };
+                    self.particles.push(particle);
+                }
+            },
+            // Hard impacts throw sparks, roughly along the contact normal
+            GameEvent::Impact { position, normal, estimated_impulse } => {
+                let count = ((estimated_impulse * SPARKS_PER_ESTIMATED_IMPULSE) as usize).min(MAX_SPARKS);
+                let spread = Range::new(-1.0, 1.0);
+                let speed = Range::new(0.3 * SPARK_SPEED, SPARK_SPEED);
+                let tangent = Vector2::new(-normal.y, normal.x);
+                for _ in 0..count {
+                    let direction = normal + tangent * spread.ind_sample(&mut self.rng);
+                    let particle = Particle {
+                        position: position,
+                        velocity: direction * speed.ind_sample(&mut self.rng),
+                        color: SPARK_COLOR,
+                        size: SPARK_SIZE,
+                        life: SPARK_LIFE,
+                        max_life: SPARK_LIFE,
+                    };
+                    self.particles.push(particle);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for particle in self.particles.iter_mut() {
+            particle.velocity = particle.velocity + GRAVITY * dt;
+            particle.position = particle.position + particle.velocity * dt;
+            particle.life -= dt;
+        }
+        self.particles.retain(|particle| particle.life > 0.0 && particle.position.y > BOTTOM - 1.0);
+    }
+
+    pub fn draw<S: Surface, F: Facade>(&self, display: &F, target: &mut S, props: &GraphicsProperties) {
+        if self.particles.is_empty() { return; }
+        let mut vertices = vec![];
+        let mut indices: Vec<u32> = vec![];
+        for (i, particle) in self.particles.iter().enumerate() {
+            // Particles fade out
+            let fade = particle.life / particle.max_life;
+            let color = [particle.color[0], particle.color[1], particle.color[2], fade];
+            let s = particle.size / 2.0;
+            for &(dx, dy) in [(-s, -s), (s, -s), (s, s), (-s, s)].iter() {
+                vertices.push(Vertex { position: [particle.position.x + dx, particle.position.y + dy], color: color });
+            }
+            let n = 4 * i as u32;
+            indices.extend_from_slice(&[n, n + 1, n + 2, n, n + 2, n + 3]);
+        }
+        draw_mesh(display, target, props, &vertices, &indices, PrimitiveType::TrianglesList);
+    }
+}
@@ -0,0 +1,58 @@
+use na::{Isometry2, Norm, Point2};
+use nphysics2d::object::RigidBodyHandle;
+use nphysics2d::world::World;
+
+use consts::*;
+use game::*;
+
+pub struct Prediction {
+    // Center of mass of the controlled object after every step
+    pub path: Vec<Point2<f32>>,
+    pub final_pose: Isometry2<f32>,
+}
+
+impl Game {
+    // Simulate the object controlled by a player a number of steps ahead, in a scratch world in
+    // which the objects around it are fixed in place.
+    pub fn predict(&self, player: usize, ticks: usize) -> Option<Prediction> {
+        let (mut world, rbh) = match self.scratch_world(player, PREDICTION_RADIUS) {
+            Some(scratch) => scratch,
+            None => return None,
+        };
+
+        let (rotate, mov) = self.player_controls(player);
+        let mut path = vec![*rbh.borrow().center_of_mass()];
+        for _ in 0..ticks {
+            apply_controls(&mut rbh.borrow_mut(), rotate, mov);
+            world.step(TIMESTEP);
+            path.push(*rbh.borrow().center_of_mass());
+        }
+        let final_pose = *rbh.borrow().position();
+        Some(Prediction {
+            path: path,
+            final_pose: final_pose,
+        })
+    }
+
+    // A new world with a copy of the object controlled by a player, and static copies of the
+    // objects within the given distance of it. Returns the world and the copy of the controlled object.
+    pub fn scratch_world(&self, player: usize, radius: f32) -> Option<(World<f32>, RigidBodyHandle<f32>)> {
+        let obj = match self.player_object(player) {
+            Some(obj) => obj,
+            None => return None,
+        };
+        let center = *obj.rbh.borrow().center_of_mass();
+
+        let mut world: World<f32> = create_world(self.well_right());
+        world.set_gravity(self.gravity());
+        let rbh = world.add_rigid_body(obj.clone_body());
+        for other in self.tetrominos() {
+            let other_center = *other.rbh.borrow().center_of_mass();
+            let is_controlled = &*other.rbh as *const _ == &*obj.rbh as *const _;
+            if !is_controlled && (other_center - center).norm() < radius {
+                world.add_rigid_body(other.clone_static_body());
+            }
+        }
+        Some((world, rbh))
+    }
+}
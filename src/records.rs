@@ -0,0 +1,123 @@
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use consts::*;
+use modes::Outcome;
+
+// The last submitted outcome, together with the record it was compared against.
+pub struct Submission {
+    pub outcome: Outcome,
+    pub previous_best: Option<Duration>,
+    // Position in the high score table, if it made it in
+    pub rank: Option<usize>,
+}
+
+// Personal bests, stored in a small text file in the home directory.
+pub struct Records {
+    path: PathBuf,
+    sprint_best: Option<Duration>,
+    // Sorted from high to low
+    ultra_scores: Vec<usize>,
+    survival_best: Option<Duration>,
+    last_submission: Option<Submission>,
+}
+
+impl Records {
+    pub fn load() -> Self {
+        let path = env::home_dir().unwrap_or_else(|| PathBuf::from(".")).join(".gliumtetris_records");
+        let mut records = Records {
+            path: path,
+            sprint_best: None,
+            ultra_scores: vec![],
+            survival_best: None,
+            last_submission: None,
+        };
+        if let Ok(file) = File::open(&records.path) {
+            for line in BufReader::new(file).lines().filter_map(|line| line.ok()) {
+                let words: Vec<_> = line.split_whitespace().collect();
+                match &words[..] {
+                    &["sprint", millis] => if let Ok(millis) = millis.parse() {
+                        records.sprint_best = Some(Duration::from_millis(millis));
+                    },
+                    &["ultra", score] => if let Ok(score) = score.parse() {
+                        records.ultra_scores.push(score);
+                    },
+                    &["survival", millis] => if let Ok(millis) = millis.parse() {
+                        records.survival_best = Some(Duration::from_millis(millis));
+                    },
+                    _ => {},
+                }
+            }
+        }
+        records.ultra_scores.sort_by(|a, b| b.cmp(a));
+        records.ultra_scores.truncate(HIGH_SCORE_ENTRIES);
+        records
+    }
+
+    fn save(&self) {
+        let mut contents = String::new();
+        if let Some(best) = self.sprint_best {
+            contents.push_str(&format!("sprint {}\n", millis(best)));
+        }
+        for score in self.ultra_scores.iter() {
+            contents.push_str(&format!("ultra {}\n", score));
+        }
+        if let Some(best) = self.survival_best {
+            contents.push_str(&format!("survival {}\n", millis(best)));
+        }
+        // Failing to save records should never interrupt the game
+        if let Ok(mut file) = File::create(&self.path) {
+            let _ = file.write_all(contents.as_bytes());
+        }
+    }
+
+    pub fn sprint_best(&self) -> Option<Duration> { self.sprint_best }
+    pub fn ultra_scores(&self) -> &[usize] { &self.ultra_scores }
+    pub fn survival_best(&self) -> Option<Duration> { self.survival_best }
+    pub fn last_submission(&self) -> Option<&Submission> { self.last_submission.as_ref() }
+
+    pub fn submit(&mut self, outcome: Outcome) {
+        let (previous_best, rank) = match outcome {
+            // There are no records for versus games
+            Outcome::ToppedOut(_) => return,
+            Outcome::SprintTime(time) => {
+                let previous_best = self.sprint_best;
+                if previous_best.map_or(true, |best| time < best) {
+                    self.sprint_best = Some(time);
+                }
+                (previous_best, None)
+            },
+            Outcome::UltraScore(score) => {
+                let rank = self.ultra_scores.iter().take_while(|&&other| other >= score).count();
+                self.ultra_scores.insert(rank, score);
+                self.ultra_scores.truncate(HIGH_SCORE_ENTRIES);
+                (None, if rank < HIGH_SCORE_ENTRIES { Some(rank) } else { None })
+            },
+            Outcome::SurvivalTime(time) => {
+                let previous_best = self.survival_best;
+                if previous_best.map_or(true, |best| time > best) {
+                    self.survival_best = Some(time);
+                }
+                (previous_best, None)
+            },
+        };
+        self.last_submission = Some(Submission {
+            outcome: outcome,
+            previous_best: previous_best,
+            rank: rank,
+        });
+        self.save();
+    }
+
+    // Forget the last submission, e.g. when a new game is started
+    pub fn clear_submission(&mut self) {
+        self.last_submission = None;
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + duration.subsec_nanos() as u64 / 1_000_000
+}
@@ -0,0 +1,477 @@
+// Recording games as logs of their inputs, and playing them back.
+//
+// A game only depends on its seed, its settings and the inputs of its players, so that's all a log
+// contains. Logs are text files:
+//
+//   REPLAY <version>
+//   GAME <mode> <seed> <line detector> <split mode> <line coverage>
+//   STEPS <number of steps>
+//   <step> <player> <action>       One line per input, in the order they were executed
+//
+// A step is one update of the game. Unlike the game's ticks, steps keep counting when the game is
+// rewound. The inputs of a step are executed right before the game is updated.
+// Modes are `marathon`, `sprint`, `ultra`, `survival`, `versus` and `coop`, line detectors are
+// `centers` or `coverage:<fraction>`, and split modes are `remove` or `cut`. The line coverage is
+// the fraction the area coverage detector uses when it's switched on during the game. Actions are `cw`,
+// `ccw`, `rotate-stop`, `left`, `right`, `move-stop`, `spawn`, `rewind`, `line-detector` and
+// `split-mode`.
+//
+// To seek quickly, a replay keeps a copy of the game every KEYFRAME_INTERVAL steps or so. Copying a
+// game rebuilds its physics world, which would change how it evolves, so copies are only made
+// when the world was just built from scratch anyway: at the start, and right after an input that
+// spawned a piece (which takes a snapshot for rewinding) or rewound the game.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use controls::Action;
+use events::GameEvent;
+use game::{Game, SplitMode};
+use lines::LineDetector;
+use modes::Mode;
+
+const LOG_VERSION: u64 = 2;
+// Steps between keyframes at least. Seeking plays this many steps, and then up to the next spawn.
+const KEYFRAME_INTERVAL: u64 = 300;
+// Playback speeds, as steps per frame
+const SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED: usize = 2;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Input {
+    pub step: u64,
+    pub player: usize,
+    pub action: Action,
+}
+
+#[derive(Clone, Debug)]
+pub struct InputLog {
+    pub mode: Mode,
+    pub seed: u32,
+    pub line_detector: LineDetector,
+    pub line_coverage: f32,
+    pub split_mode: SplitMode,
+    pub steps: u64,
+    pub inputs: Vec<Input>,
+}
+
+impl InputLog {
+    // An empty log for a game that was just reset
+    pub fn new(game: &Game) -> Self {
+        InputLog {
+            mode: game.mode(),
+            seed: game.seed(),
+            line_detector: game.line_detector(),
+            line_coverage: game.line_coverage(),
+            split_mode: game.split_mode(),
+            steps: 0,
+            inputs: vec![],
+        }
+    }
+
+    // The game as it was when the log started
+    pub fn start(&self) -> Game {
+        let mut game = Game::new();
+        game.set_mode(self.mode);
+        game.reseed(self.seed);
+        game.set_line_coverage(self.line_coverage);
+        game.set_line_detector(self.line_detector);
+        if game.split_mode() != self.split_mode {
+            game.toggle_split_mode();
+        }
+        game
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut contents = format!("REPLAY {}\n", LOG_VERSION);
+        let detector = match self.line_detector {
+            LineDetector::BlockCenters => "centers".to_owned(),
+            LineDetector::AreaCoverage(fraction) => format!("coverage:{}", fraction),
+        };
+        let split_mode = match self.split_mode {
+            SplitMode::Remove => "remove",
+            SplitMode::Cut => "cut",
+        };
+        contents.push_str(&format!("GAME {} {} {} {} {}\n", mode_name(self.mode), self.seed, detector, split_mode,
+                                   self.line_coverage));
+        contents.push_str(&format!("STEPS {}\n", self.steps));
+        for input in self.inputs.iter() {
+            let action = action_name(&input.action).expect("only game inputs are recorded");
+            contents.push_str(&format!("{} {} {}\n", input.step, input.player, action));
+        }
+        let mut file = try!(File::create(path));
+        file.write_all(contents.as_bytes())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = try!(File::open(path).map_err(|error| format!("can't open {}: {}", path.display(), error)));
+        let mut log = None;
+        let mut steps = None;
+        let mut inputs = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = try!(line.map_err(|error| format!("can't read {}: {}", path.display(), error)));
+            let malformed = || format!("malformed line in {}: {:?}", path.display(), line);
+            let words: Vec<_> = line.split_whitespace().collect();
+            match &words[..] {
+                &["REPLAY", version] => if version.parse::<u64>() != Ok(LOG_VERSION) {
+                    return Err(format!("unsupported replay version {}", version));
+                },
+                &["GAME", mode, seed, detector, split_mode, coverage] => {
+                    let line_detector = match detector {
+                        "centers" => LineDetector::BlockCenters,
+                        _ if detector.starts_with("coverage:") => LineDetector::AreaCoverage(
+                            try!(detector["coverage:".len()..].parse().map_err(|_| malformed()))),
+                        _ => return Err(malformed()),
+                    };
+                    log = Some(InputLog {
+                        mode: try!(mode_by_name(mode).ok_or_else(&malformed)),
+                        seed: try!(seed.parse().map_err(|_| malformed())),
+                        line_detector: line_detector,
+                        line_coverage: try!(coverage.parse().map_err(|_| malformed())),
+                        split_mode: match split_mode {
+                            "remove" => SplitMode::Remove,
+                            "cut" => SplitMode::Cut,
+                            _ => return Err(malformed()),
+                        },
+                        steps: 0,
+                        inputs: vec![],
+                    });
+                },
+                &["STEPS", count] => steps = Some(try!(count.parse::<u64>().map_err(|_| malformed()))),
+                &[step, player, action] => inputs.push(Input {
+                    step: try!(step.parse().map_err(|_| malformed())),
+                    player: try!(player.parse().map_err(|_| malformed())),
+                    action: try!(action_by_name(action).ok_or_else(&malformed)),
+                }),
+                &[] => {},
+                _ => return Err(malformed()),
+            }
+        }
+
+        let mut log = try!(log.ok_or_else(|| format!("{} isn't a replay", path.display())));
+        log.steps = try!(steps.ok_or_else(|| format!("{} doesn't say how long the replay is", path.display())));
+        // Inputs are played in order, and only by players that are in the game
+        let mut last_step = 0;
+        for input in inputs.iter() {
+            if input.step < last_step || input.step >= log.steps || input.player >= log.mode.players() {
+                return Err(format!("{} has an input out of place at step {}", path.display(), input.step));
+            }
+            last_step = input.step;
+        }
+        log.inputs = inputs;
+        Ok(log)
+    }
+}
+
+// True for the actions that change how a game plays out, which are what a log records.
+// Resets and mode changes start a new log instead.
+pub fn is_game_input(action: &Action) -> bool {
+    action_name(action).is_some()
+}
+
+fn mode_name(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Marathon => "marathon",
+        Mode::Sprint   => "sprint",
+        Mode::Ultra    => "ultra",
+        Mode::Survival => "survival",
+        Mode::Versus   => "versus",
+        Mode::Coop     => "coop",
+    }
+}
+
+fn mode_by_name(name: &str) -> Option<Mode> {
+    Some(match name {
+        "marathon" => Mode::Marathon,
+        "sprint"   => Mode::Sprint,
+        "ultra"    => Mode::Ultra,
+        "survival" => Mode::Survival,
+        "versus"   => Mode::Versus,
+        "coop"     => Mode::Coop,
+        _ => return None,
+    })
+}
+
+fn action_name(action: &Action) -> Option<&'static str> {
+    Some(match *action {
+        Action::RotateCW           => "cw",
+        Action::RotateCCW          => "ccw",
+        Action::RotateStop         => "rotate-stop",
+        Action::MoveLeft           => "left",
+        Action::MoveRight          => "right",
+        Action::MoveStop           => "move-stop",
+        Action::TrySpawn           => "spawn",
+        Action::Rewind             => "rewind",
+        Action::ToggleLineDetector => "line-detector",
+        Action::ToggleSplitMode    => "split-mode",
+        _ => return None,
+    })
+}
+
+fn action_by_name(name: &str) -> Option<Action> {
+    Some(match name {
+        "cw"            => Action::RotateCW,
+        "ccw"           => Action::RotateCCW,
+        "rotate-stop"   => Action::RotateStop,
+        "left"          => Action::MoveLeft,
+        "right"         => Action::MoveRight,
+        "move-stop"     => Action::MoveStop,
+        "spawn"         => Action::TrySpawn,
+        "rewind"        => Action::Rewind,
+        "line-detector" => Action::ToggleLineDetector,
+        "split-mode"    => Action::ToggleSplitMode,
+        _ => return None,
+    })
+}
+
+// Records the inputs of a game into a log file
+pub struct Recorder {
+    path: PathBuf,
+    log: InputLog,
+}
+
+impl Recorder {
+    // Start recording a game that was just reset
+    pub fn new(path: PathBuf, game: &Game) -> Self {
+        Recorder { path: path, log: InputLog::new(game) }
+    }
+
+    // Record an action that's executed before the next update of the game
+    pub fn record(&mut self, player: usize, action: Action) {
+        if is_game_input(&action) {
+            self.log.inputs.push(Input { step: self.log.steps, player: player, action: action });
+        }
+    }
+
+    // Call after every update of the game
+    pub fn update(&mut self) {
+        self.log.steps += 1;
+    }
+
+    // Save the log of the current game, and start recording a game that was just reset
+    pub fn restart(&mut self, game: &Game) -> io::Result<()> {
+        let saved = self.save();
+        self.log = InputLog::new(game);
+        saved
+    }
+
+    // Save the log, unless nothing was played. It replaces the log of the previous game.
+    pub fn save(&self) -> io::Result<()> {
+        if self.log.inputs.is_empty() {
+            return Ok(());
+        }
+        self.log.save(&self.path)
+    }
+}
+
+struct Keyframe {
+    step: u64,
+    // Index of the first input that wasn't executed yet
+    next_input: usize,
+    game: Game,
+}
+
+// Plays back a log, at different speeds and from any step
+pub struct Replay {
+    log: InputLog,
+    game: Game,
+    step: u64,
+    next_input: usize,
+    keyframes: Vec<Keyframe>,
+    // Steps after which lines were cleared, with the number of lines
+    clears: Vec<(u64, usize)>,
+    paused: bool,
+    speed: usize,
+    // Part of a step left over from the last update at a fractional speed
+    partial_step: f32,
+}
+
+impl Replay {
+    // Play the whole log once, to take the keyframes and find the line clears
+    pub fn new(log: InputLog) -> Self {
+        let mut game = log.start();
+        let keyframe = Keyframe { step: 0, next_input: 0, game: game.fork_with_history() };
+        let mut replay = Replay {
+            log: log,
+            game: game,
+            step: 0,
+            next_input: 0,
+            keyframes: vec![keyframe],
+            clears: vec![],
+            paused: false,
+            speed: NORMAL_SPEED,
+            partial_step: 0.0,
+        };
+        while replay.step < replay.log.steps {
+            replay.advance();
+            for event in replay.game.drain_events() {
+                if let GameEvent::LinesCleared { ref heights, .. } = event {
+                    replay.clears.push((replay.step, heights.len()));
+                }
+            }
+        }
+        replay.seek(0);
+        replay
+    }
+
+    pub fn game(&self) -> &Game { &self.game }
+    pub fn log(&self) -> &InputLog { &self.log }
+    pub fn step(&self) -> u64 { self.step }
+    pub fn steps(&self) -> u64 { self.log.steps }
+    pub fn clears(&self) -> &[(u64, usize)] { &self.clears }
+    pub fn paused(&self) -> bool { self.paused }
+    pub fn speed(&self) -> f32 { SPEEDS[self.speed] }
+    pub fn finished(&self) -> bool { self.step >= self.log.steps }
+
+    // Pausing at the end and playing again starts over
+    pub fn toggle_pause(&mut self) {
+        if self.paused && self.finished() {
+            self.seek(0);
+        }
+        self.paused = !self.paused;
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed + 1).min(SPEEDS.len() - 1);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = self.speed.saturating_sub(1);
+    }
+
+    // Play at the current speed, unless paused. Returns the events of the steps that were played.
+    pub fn update(&mut self) -> Vec<GameEvent> {
+        let mut events = vec![];
+        if self.paused {
+            return events;
+        }
+        self.partial_step += SPEEDS[self.speed];
+        while self.partial_step >= 1.0 && !self.finished() {
+            self.advance();
+            events.extend(self.game.drain_events());
+            self.partial_step -= 1.0;
+        }
+        if self.finished() {
+            self.paused = true;
+            self.partial_step = 0.0;
+        }
+        events
+    }
+
+    // Jump to a step, from the last keyframe before it. Events of the steps in between are dropped.
+    pub fn seek(&mut self, step: u64) {
+        let step = step.min(self.log.steps);
+        // Keyframes after the start are taken partway through the inputs of their step
+        let index = self.keyframes.iter().rposition(|keyframe| keyframe.step < step).unwrap_or(0);
+        // Moving forward within the same stretch between keyframes doesn't need the keyframe
+        if self.step > step || (index > 0 && self.step <= self.keyframes[index].step) {
+            let keyframe = &mut self.keyframes[index];
+            self.game = keyframe.game.fork_with_history();
+            self.step = keyframe.step;
+            self.next_input = keyframe.next_input;
+        }
+        while self.step < step {
+            self.advance();
+        }
+        self.game.drain_events();
+        self.partial_step = 0.0;
+    }
+
+    // The step of the first line clear after the current step, if any
+    pub fn next_clear(&self) -> Option<u64> {
+        self.clears.iter().map(|&(step, _)| step).find(|&step| step > self.step)
+    }
+
+    // The step of the last line clear before the current step, if any
+    pub fn previous_clear(&self) -> Option<u64> {
+        self.clears.iter().rev().map(|&(step, _)| step).find(|&step| step < self.step)
+    }
+
+    // Play one step: execute the inputs of the step and update the game. A keyframe is taken
+    // after an input when it's time and the game's world is fresh.
+    fn advance(&mut self) {
+        while let Some(&input) = self.log.inputs.get(self.next_input) {
+            if input.step != self.step {
+                break;
+            }
+            self.game.execute_player_action(input.player, input.action);
+            self.next_input += 1;
+
+            let due = self.keyframes.last().unwrap().step + KEYFRAME_INTERVAL <= self.step;
+            if due && self.game.has_fresh_world() {
+                let keyframe = Keyframe { step: self.step, next_input: self.next_input, game: self.game.fork_with_history() };
+                self.keyframes.push(keyframe);
+            }
+        }
+        self.game.update();
+        self.step += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use rand::Rng;
+
+    use controls::Action;
+    use game::Game;
+    use game::tests::test_rng;
+    use netcode::pose_checksum;
+    use super::{InputLog, Recorder, Replay};
+
+    const STEPS: u64 = 1000;
+    // Far enough in for a keyframe or two before it
+    const STEP: u64 = 700;
+
+    const ACTIONS: [Action; 7] = [
+        Action::RotateCW, Action::RotateCCW, Action::RotateStop,
+        Action::MoveLeft, Action::MoveRight, Action::MoveStop,
+        Action::TrySpawn,
+    ];
+
+    #[test]
+    fn replays_match_the_recorded_game() {
+        let mut rng = test_rng();
+        let mut game = Game::new();
+        game.reseed(99);
+        let path = env::temp_dir().join("gliumtetris-replay-test.log");
+        let mut recorder = Recorder::new(path.clone(), &game);
+        // The checksum of the game after each step
+        let mut checksums = vec![];
+        for _ in 0..STEPS {
+            if rng.gen_weighted_bool(10) {
+                let action = *rng.choose(&ACTIONS).unwrap();
+                recorder.record(0, action);
+                game.execute_action(action);
+            }
+            game.update();
+            recorder.update();
+            checksums.push(pose_checksum(&[&game]));
+        }
+        recorder.save().unwrap();
+        let log = InputLog::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(log.steps, STEPS);
+        assert_eq!(log.inputs, recorder.log.inputs);
+
+        // Played from the start
+        let mut replay = Replay::new(log);
+        assert!(replay.keyframes.iter().any(|keyframe| keyframe.step > 0 && keyframe.step < STEP));
+        while replay.step() < STEP {
+            replay.update();
+        }
+        assert_eq!(pose_checksum(&[replay.game()]), checksums[STEP as usize - 1]);
+
+        // Restored from keyframes, going forward and back
+        replay.seek(STEPS);
+        assert_eq!(pose_checksum(&[replay.game()]), checksums[STEPS as usize - 1]);
+        replay.seek(STEP);
+        assert_eq!(pose_checksum(&[replay.game()]), checksums[STEP as usize - 1]);
+        replay.seek(0);
+        replay.seek(STEP);
+        assert_eq!(pose_checksum(&[replay.game()]), checksums[STEP as usize - 1]);
+    }
+}
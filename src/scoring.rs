@@ -0,0 +1,181 @@
+use std::time::Duration;
+
+use consts::*;
+
+// Breakdown of the points awarded for a single line clear.
+#[derive(Clone, Debug)]
+pub struct ScoreEvent {
+    pub lines: usize,
+    pub base: usize,
+    pub multi_line_bonus: usize,
+    pub combo: usize,
+    pub combo_bonus: usize,
+    pub pieces: usize,
+    pub efficiency_bonus: usize,
+}
+
+impl ScoreEvent {
+    pub fn total(&self) -> usize {
+        self.base + self.multi_line_bonus + self.combo_bonus + self.efficiency_bonus
+    }
+
+    // Short description of the event, suitable for the HUD
+    pub fn describe(&self) -> String {
+        let mut parts = vec![format!("{} line{} +{}", self.lines, if self.lines == 1 { "" } else { "s" }, self.base)];
+        if self.multi_line_bonus > 0 {
+            parts.push(format!("multi +{}", self.multi_line_bonus));
+        }
+        if self.combo_bonus > 0 {
+            parts.push(format!("combo x{} +{}", self.combo, self.combo_bonus));
+        }
+        if self.efficiency_bonus > 0 {
+            parts.push(format!("{} pieces +{}", self.pieces, self.efficiency_bonus));
+        }
+        parts.join(", ")
+    }
+}
+
+#[derive(Clone)]
+pub struct Scoring {
+    score: usize,
+    // Game times of the last clear, and of the last score event
+    last_score: Option<Duration>,
+    combo: usize,
+    pieces_since_clear: usize,
+    last_event: Option<(Duration, ScoreEvent)>,
+}
+
+impl Scoring {
+    pub fn new() -> Self {
+        Scoring {
+            score: 0,
+            last_score: None,
+            combo: 0,
+            pieces_since_clear: 0,
+            last_event: None,
+        }
+    }
+
+    pub fn score(&self) -> usize { self.score }
+
+    // The most recent score event, as long as it's recent enough to be shown. An event from
+    // after `now` isn't shown.
+    pub fn recent_event(&self, now: Duration) -> Option<&ScoreEvent> {
+        match self.last_event {
+            Some((time, ref event)) if within(now, time, SCORE_DISPLAY_MS) => Some(event),
+            _ => None,
+        }
+    }
+
+    pub fn piece_spawned(&mut self) {
+        self.pieces_since_clear += 1;
+    }
+
+    pub fn lines_cleared(&mut self, lines: usize, blocks_per_line: usize, now: Duration) -> ScoreEvent {
+        // Clearing again within the combo window extends the combo, otherwise it's reset.
+        self.combo = match self.last_score {
+            Some(time) if within(now, time, COMBO_WINDOW_MS) => self.combo + 1,
+            _ => 0,
+        };
+
+        let base = LINE_SCORE * lines;
+        // Every extra line cleared at once is worth more than the previous one
+        let multi_line_bonus = LINE_SCORE * (lines * lines - lines);
+        let combo_bonus = (base + multi_line_bonus) * self.combo * COMBO_PERCENT / 100;
+        // The fewest pieces that could possibly have filled these lines
+        let ideal_pieces = (lines * blocks_per_line + POLYOMINOS[0].len() - 1) / POLYOMINOS[0].len();
+        let efficiency_bonus = match self.pieces_since_clear {
+            0 => EFFICIENCY_SCORE * lines,
+            pieces => ::std::cmp::min(EFFICIENCY_SCORE * lines, EFFICIENCY_SCORE * lines * ideal_pieces / pieces),
+        };
+
+        let event = ScoreEvent {
+            lines: lines,
+            base: base,
+            multi_line_bonus: multi_line_bonus,
+            combo: self.combo,
+            combo_bonus: combo_bonus,
+            pieces: self.pieces_since_clear,
+            efficiency_bonus: efficiency_bonus,
+        };
+
+        self.score += event.total();
+        self.last_score = Some(now);
+        self.pieces_since_clear = 0;
+        self.last_event = Some((now, event.clone()));
+        event
+    }
+
+    pub fn reset(&mut self) {
+        *self = Scoring::new();
+    }
+}
+
+// Whether `time` is less than the given number of milliseconds before `now`
+fn within(now: Duration, time: Duration, ms: u64) -> bool {
+    now.checked_sub(time).map_or(false, |elapsed| elapsed < Duration::from_millis(ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use consts::{COMBO_PERCENT, COMBO_WINDOW_MS, EFFICIENCY_SCORE, LINE_SCORE, SCORE_DISPLAY_MS};
+    use super::Scoring;
+
+    fn ms(ms: u64) -> Duration { Duration::from_millis(ms) }
+
+    #[test]
+    fn points_follow_the_table() {
+        let mut scoring = Scoring::new();
+        // Ten blocks per line take three tetrominos at least
+        for _ in 0..3 {
+            scoring.piece_spawned();
+        }
+        let single = scoring.lines_cleared(1, 10, ms(0));
+        assert_eq!((single.base, single.multi_line_bonus, single.combo_bonus), (LINE_SCORE, 0, 0));
+        assert_eq!(single.efficiency_bonus, EFFICIENCY_SCORE);
+
+        for _ in 0..6 {
+            scoring.piece_spawned();
+        }
+        let double = scoring.lines_cleared(2, 10, ms(COMBO_WINDOW_MS * 2));
+        assert_eq!((double.base, double.multi_line_bonus, double.combo_bonus), (2 * LINE_SCORE, 2 * LINE_SCORE, 0));
+        // Six pieces for the ideal five
+        assert_eq!(double.efficiency_bonus, EFFICIENCY_SCORE * 2 * 5 / 6);
+
+        for _ in 0..20 {
+            scoring.piece_spawned();
+        }
+        let tetris = scoring.lines_cleared(4, 10, ms(COMBO_WINDOW_MS * 4));
+        assert_eq!((tetris.base, tetris.multi_line_bonus), (4 * LINE_SCORE, 12 * LINE_SCORE));
+        assert_eq!(tetris.efficiency_bonus, EFFICIENCY_SCORE * 4 * 10 / 20);
+
+        assert_eq!(scoring.score(), single.total() + double.total() + tetris.total());
+    }
+
+    #[test]
+    fn clears_within_the_window_make_a_combo() {
+        let mut scoring = Scoring::new();
+        assert_eq!(scoring.lines_cleared(1, 10, ms(1000)).combo, 0);
+        let second = scoring.lines_cleared(1, 10, ms(1000 + COMBO_WINDOW_MS - 1));
+        assert_eq!(second.combo, 1);
+        assert_eq!(second.combo_bonus, (second.base + second.multi_line_bonus) * COMBO_PERCENT / 100);
+        assert_eq!(scoring.lines_cleared(1, 10, ms(1000 + 2 * COMBO_WINDOW_MS - 2)).combo, 2);
+        // Just outside the window
+        assert_eq!(scoring.lines_cleared(1, 10, ms(1000 + 3 * COMBO_WINDOW_MS - 2)).combo, 0);
+        // A clock that went backwards breaks the combo rather than panicking
+        assert_eq!(scoring.lines_cleared(1, 10, ms(0)).combo, 0);
+    }
+
+    #[test]
+    fn events_are_shown_for_a_while() {
+        let mut scoring = Scoring::new();
+        assert!(scoring.recent_event(ms(0)).is_none());
+        scoring.lines_cleared(1, 10, ms(1000));
+        assert!(scoring.recent_event(ms(1000)).is_some());
+        assert!(scoring.recent_event(ms(1000 + SCORE_DISPLAY_MS - 1)).is_some());
+        assert!(scoring.recent_event(ms(1000 + SCORE_DISPLAY_MS)).is_none());
+        assert!(scoring.recent_event(ms(999)).is_none());
+    }
+}
@@ -0,0 +1,456 @@
+// Streams the state of running games to spectators over TCP, and reads the stream back.
+//
+// A spectator connects to the address given with `--spectate`, and receives a header followed by
+// a frame for every tick until it disconnects. Spectators that can't keep up skip frames.
+// All numbers are little-endian, and floats are IEEE 754 single precision.
+//
+//   header:  "GTSS", version (u16)
+//   frame:   size of the rest of the frame in bytes (u32, at most 1 MiB), tick (u64), number of games (u8), games
+//   game:    mode name (u8 length, then UTF-8), score (u32), level (u16), lines (u32),
+//            width of the well (f32), number of bodies (u16), bodies
+//   body:    x, y and angle of the body (f32 each), color (3 u8s), flags (u8, bit 0 = controlled
+//            by a player, bits 1 to 7 = which player), number of blocks (u8), blocks
+//   block:   x, y of the center of the block in the frame of the body (f32 each), flags (u8,
+//            1 = in a line that's being cleared), number of points (u8), points as x, y relative to
+//            the center (f32 each). Whole blocks have no points and the shape of a standard block;
+//            the fragments of cut blocks list their outline.
+//
+// The well's bottom left corner is at (0, 0), and it's 16 units high.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::thread;
+
+use na::{Point2, Rotation};
+use ncollide::shape;
+
+use consts::*;
+use game::{block, BlockState, Game};
+
+// Like `try!`, for options
+macro_rules! try_opt {
+    ($e:expr) => (match $e { Some(value) => value, None => return None })
+}
+
+const MAGIC: &'static [u8; 4] = b"GTSS";
+const VERSION: u16 = 2;
+// Larger frames are taken to be garbage, rather than allocated
+const MAX_FRAME_SIZE: usize = 1 << 20;
+// Frames queued for a spectator at most, before frames are skipped
+const QUEUED_FRAMES: usize = 8;
+
+// The state of one game in a frame of the stream
+#[derive(Clone, Debug)]
+pub struct GameFrame {
+    pub mode: String,
+    pub score: usize,
+    pub level: usize,
+    pub lines: usize,
+    pub well_width: f32,
+    pub bodies: Vec<BodyFrame>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BodyFrame {
+    pub position: Point2<f32>,
+    pub angle: f32,
+    pub color: [f32; 3],
+    // The player controlling the body, if any
+    pub player: Option<usize>,
+    pub blocks: Vec<BlockFrame>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BlockFrame {
+    // The outline of the block, in the frame of the body
+    pub outline: Vec<Point2<f32>>,
+    pub clearing: bool,
+}
+
+impl BodyFrame {
+    // The outline of a block in world coordinates
+    pub fn world_outline(&self, block: &BlockFrame) -> Vec<Point2<f32>> {
+        let (sin, cos) = self.angle.sin_cos();
+        block.outline.iter()
+            .map(|pt| Point2::new(self.position.x + cos * pt.x - sin * pt.y, self.position.y + sin * pt.x + cos * pt.y))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub tick: u64,
+    pub games: Vec<GameFrame>,
+}
+
+// Encode the current state of the given games as a frame, including its size
+pub fn encode_frame(tick: u64, games: &[&Game]) -> Vec<u8> {
+    let standard = block(BLOCK_SIZE/2.0, CORNER_RADIUS, EDGES_PER_CORNER);
+    let mut out = Encoder { bytes: vec![] };
+    out.u64(tick);
+    out.u8(games.len() as u8);
+    for game in games {
+        out.string(game.mode().name());
+        out.u32(game.score() as u32);
+        out.u16(game.level() as u16);
+        out.u32(game.lines() as u32);
+        out.f32(game.well_right() - LEFT);
+        out.u16(game.tetrominos().len() as u16);
+        for tetr in game.tetrominos() {
+            let rb = tetr.rbh.borrow();
+            let iso = rb.position();
+            out.f32(iso.translation.x);
+            out.f32(iso.translation.y);
+            out.f32(iso.rotation().x);
+            for &c in tetr.color.iter() {
+                out.u8((c.max(0.0).min(1.0) * 255.0).round() as u8);
+            }
+            let player = (0..game.players()).find(|&player| match game.player_object(player) {
+                Some(obj) => &*obj.rbh as *const _ == &*tetr.rbh as *const _,
+                None => false,
+            });
+            out.u8(match player {
+                Some(player) => 1 | (player as u8) << 1,
+                None => 0,
+            });
+
+            let blocks = tetr.blocks();
+            out.u8(blocks.len() as u8);
+            for (&(inner_iso, ref shape), &state) in blocks.iter().zip(tetr.states.iter()) {
+                out.f32(inner_iso.translation.x);
+                out.f32(inner_iso.translation.y);
+                out.u8(if state == BlockState::Clearing { 1 } else { 0 });
+                let points = shape.as_shape::<shape::ConvexHull<Point2<f32>>>().unwrap().points();
+                if points == &standard[..] {
+                    out.u8(0);
+                } else {
+                    out.u8(points.len() as u8);
+                    for pt in points {
+                        out.f32(pt.x);
+                        out.f32(pt.y);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut frame = Encoder { bytes: vec![] };
+    frame.u32(out.bytes.len() as u32);
+    frame.bytes.extend(out.bytes);
+    frame.bytes
+}
+
+// Read the header of a stream
+pub fn read_header<R: Read>(reader: &mut R) -> Result<(), String> {
+    let mut header = [0; 6];
+    try!(reader.read_exact(&mut header).map_err(|error| format!("can't read the header: {}", error)));
+    if &header[..4] != &MAGIC[..] {
+        return Err("not a spectator stream".to_owned());
+    }
+    let version = header[4] as u16 | (header[5] as u16) << 8;
+    if version != VERSION {
+        return Err(format!("unsupported stream version {}", version));
+    }
+    Ok(())
+}
+
+// Read the next frame of a stream
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<Frame, String> {
+    let mut size = [0; 4];
+    try!(reader.read_exact(&mut size).map_err(|error| format!("the stream ended: {}", error)));
+    let size = size.iter().rev().fold(0, |size, &byte| size << 8 | byte as usize);
+    if size > MAX_FRAME_SIZE {
+        return Err(format!("a frame of {} bytes is too large", size));
+    }
+    let mut bytes = vec![0; size];
+    try!(reader.read_exact(&mut bytes).map_err(|error| format!("the stream ended: {}", error)));
+    decode_frame(&bytes).ok_or_else(|| "malformed frame".to_owned())
+}
+
+fn decode_frame(bytes: &[u8]) -> Option<Frame> {
+    let standard = block(BLOCK_SIZE/2.0, CORNER_RADIUS, EDGES_PER_CORNER);
+    let mut input = Decoder { bytes: bytes, position: 0 };
+    let tick = try_opt!(input.u64());
+    let mut games = vec![];
+    for _ in 0..try_opt!(input.u8()) {
+        let mode = try_opt!(input.string());
+        let score = try_opt!(input.u32()) as usize;
+        let level = try_opt!(input.u16()) as usize;
+        let lines = try_opt!(input.u32()) as usize;
+        let well_width = try_opt!(input.f32());
+        let mut bodies = vec![];
+        for _ in 0..try_opt!(input.u16()) {
+            let position = Point2::new(try_opt!(input.f32()), try_opt!(input.f32()));
+            let angle = try_opt!(input.f32());
+            let mut color = [0.0; 3];
+            for c in color.iter_mut() {
+                *c = try_opt!(input.u8()) as f32 / 255.0;
+            }
+            let flags = try_opt!(input.u8());
+            let player = if flags & 1 != 0 { Some((flags >> 1) as usize) } else { None };
+            let mut blocks = vec![];
+            for _ in 0..try_opt!(input.u8()) {
+                let center = Point2::new(try_opt!(input.f32()), try_opt!(input.f32()));
+                let clearing = try_opt!(input.u8()) & 1 != 0;
+                let mut points = vec![];
+                for _ in 0..try_opt!(input.u8()) {
+                    points.push(Point2::new(try_opt!(input.f32()), try_opt!(input.f32())));
+                }
+                if points.is_empty() {
+                    points = standard.clone();
+                }
+                blocks.push(BlockFrame {
+                    outline: points.into_iter().map(|pt| center + pt.to_vector()).collect(),
+                    clearing: clearing,
+                });
+            }
+            bodies.push(BodyFrame { position: position, angle: angle, color: color, player: player, blocks: blocks });
+        }
+        games.push(GameFrame { mode: mode, score: score, level: level, lines: lines, well_width: well_width, bodies: bodies });
+    }
+    Some(Frame { tick: tick, games: games })
+}
+
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn u8(&mut self, value: u8) { self.bytes.push(value); }
+    fn u16(&mut self, value: u16) { self.little_endian(value as u64, 2); }
+    fn u32(&mut self, value: u32) { self.little_endian(value as u64, 4); }
+    fn u64(&mut self, value: u64) { self.little_endian(value, 8); }
+    fn f32(&mut self, value: f32) { self.u32(value.to_bits()); }
+
+    fn string(&mut self, value: &str) {
+        self.u8(value.len() as u8);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn little_endian(&mut self, value: u64, size: usize) {
+        for i in 0..size {
+            self.bytes.push((value >> (8 * i)) as u8);
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn u8(&mut self) -> Option<u8> { self.little_endian(1).map(|value| value as u8) }
+    fn u16(&mut self) -> Option<u16> { self.little_endian(2).map(|value| value as u16) }
+    fn u32(&mut self) -> Option<u32> { self.little_endian(4).map(|value| value as u32) }
+    fn u64(&mut self) -> Option<u64> { self.little_endian(8) }
+    fn f32(&mut self) -> Option<f32> { self.u32().map(f32::from_bits) }
+
+    fn string(&mut self) -> Option<String> {
+        let len = try_opt!(self.u8()) as usize;
+        let bytes = try_opt!(self.take(len));
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+
+    fn little_endian(&mut self, size: usize) -> Option<u64> {
+        self.take(size).map(|bytes| bytes.iter().rev().fold(0, |value, &byte| value << 8 | byte as u64))
+    }
+
+    fn take(&mut self, size: usize) -> Option<&'a [u8]> {
+        if self.position + size > self.bytes.len() { return None; }
+        let bytes = &self.bytes[self.position..self.position + size];
+        self.position += size;
+        Some(bytes)
+    }
+}
+
+// Accepts spectators, and sends them every frame
+pub struct SpectatorServer {
+    listener: TcpListener,
+    spectators: Vec<SyncSender<Arc<Vec<u8>>>>,
+    max_frame_size: usize,
+    // Whether the last frame was too large to send, so that it's only reported once
+    oversized: bool,
+}
+
+impl SpectatorServer {
+    // Listen for spectators on the given address, e.g. "0.0.0.0:7780"
+    pub fn bind(address: &str) -> io::Result<Self> {
+        let listener = try!(TcpListener::bind(address));
+        try!(listener.set_nonblocking(true));
+        Ok(SpectatorServer {
+            listener: listener,
+            spectators: vec![],
+            max_frame_size: MAX_FRAME_SIZE,
+            oversized: false,
+        })
+    }
+
+    pub fn spectators(&self) -> usize { self.spectators.len() }
+
+    // Let in new spectators, and send the current state of the games to everyone
+    pub fn broadcast(&mut self, tick: u64, games: &[&Game]) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if let Ok(spectator) = spectate(stream) {
+                self.spectators.push(spectator);
+            }
+        }
+        if self.spectators.is_empty() { return; }
+
+        let frame = encode_frame(tick, games);
+        // Spectators would drop the connection on a frame this large, so it's skipped instead
+        let size = frame.len() - 4;
+        if size > self.max_frame_size {
+            if !self.oversized {
+                println!("Not streaming tick {} to spectators: the frame takes {} bytes, over the limit of {}",
+                         tick, size, self.max_frame_size);
+            }
+            self.oversized = true;
+            return;
+        }
+        self.oversized = false;
+
+        let frame = Arc::new(frame);
+        // Spectators that disconnected are dropped, and the ones that are behind skip this frame
+        self.spectators.retain(|spectator| match spectator.try_send(frame.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+// Send the header and the queued frames to a spectator, on a separate thread
+fn spectate(mut stream: TcpStream) -> io::Result<SyncSender<Arc<Vec<u8>>>> {
+    try!(stream.set_nonblocking(false));
+    try!(stream.set_nodelay(true));
+    let (sender, frames) = mpsc::sync_channel::<Arc<Vec<u8>>>(QUEUED_FRAMES);
+    thread::spawn(move || {
+        let header = [MAGIC[0], MAGIC[1], MAGIC[2], MAGIC[3], VERSION as u8, (VERSION >> 8) as u8];
+        if stream.write_all(&header).is_err() { return; }
+        for frame in frames.iter() {
+            if stream.write_all(&frame).is_err() { return; }
+        }
+    });
+    Ok(sender)
+}
+
+// A connection to a game that's being streamed. Frames are read on a separate thread.
+pub struct Spectator {
+    incoming: Receiver<Result<Frame, String>>,
+    error: Option<String>,
+}
+
+impl Spectator {
+    pub fn connect(address: &str) -> Result<Self, String> {
+        let mut stream = try!(TcpStream::connect(address).map_err(|error| format!("can't connect to {}: {}", address, error)));
+        try!(read_header(&mut stream));
+        let (sender, incoming) = mpsc::channel();
+        thread::spawn(move || {
+            loop {
+                let frame = read_frame(&mut stream);
+                let failed = frame.is_err();
+                if sender.send(frame).is_err() || failed { return; }
+            }
+        });
+        Ok(Spectator { incoming: incoming, error: None })
+    }
+
+    // The most recent frame that arrived since the last call, if any
+    pub fn latest(&mut self) -> Option<Frame> {
+        let mut latest = None;
+        loop {
+            match self.incoming.try_recv() {
+                Ok(Ok(frame)) => latest = Some(frame),
+                Ok(Err(error)) => self.error = Some(error),
+                Err(TryRecvError::Empty) => return latest,
+                Err(TryRecvError::Disconnected) => {
+                    if self.error.is_none() {
+                        self.error = Some("the connection was closed".to_owned());
+                    }
+                    return latest;
+                },
+            }
+        }
+    }
+
+    // Why the stream ended, if it did
+    pub fn error(&self) -> Option<&str> { self.error.as_ref().map(|error| &error[..]) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpStream;
+    use std::thread;
+
+    use controls::Action;
+    use game::Game;
+    use super::{decode_frame, encode_frame, read_frame, read_header, SpectatorServer, MAX_FRAME_SIZE};
+
+    #[test]
+    fn frames_round_trip() {
+        let mut game = Game::new();
+        game.reseed(5);
+        for tick in 0..600 {
+            if tick % 60 == 0 {
+                game.execute_action(Action::TrySpawn);
+            }
+            game.update();
+        }
+        assert!(game.player_object(0).is_some());
+
+        let bytes = encode_frame(1234, &[&game, &game]);
+        let frame = read_frame(&mut &bytes[..]).unwrap();
+        assert_eq!(frame.tick, 1234);
+        assert_eq!(frame.games.len(), 2);
+        let body_frames = &frame.games[1].bodies;
+        assert_eq!(body_frames.len(), game.tetrominos().len());
+        for (body, tetr) in body_frames.iter().zip(game.tetrominos()) {
+            let iso = *tetr.rbh.borrow().position();
+            assert_eq!(body.position, iso.translation.to_point());
+            assert_eq!(body.blocks.len(), tetr.blocks().len());
+            let controlled = game.player_object(0).map_or(false, |obj| &*obj.rbh as *const _ == &*tetr.rbh as *const _);
+            assert_eq!(body.player, if controlled { Some(0) } else { None });
+        }
+        assert_eq!(frame.games[0].score, game.score());
+        assert_eq!(frame.games[0].mode, game.mode().name());
+    }
+
+    #[test]
+    fn truncated_and_oversized_frames_are_rejected() {
+        let bytes = encode_frame(0, &[&Game::new()]);
+        assert!(decode_frame(&bytes[4..bytes.len() - 1]).is_none());
+        let size = MAX_FRAME_SIZE as u32 + 1;
+        let header = [size as u8, (size >> 8) as u8, (size >> 16) as u8, (size >> 24) as u8];
+        assert!(read_frame(&mut &header[..]).is_err());
+    }
+
+    #[test]
+    fn oversized_frames_are_never_sent() {
+        let mut server = SpectatorServer::bind("127.0.0.1:0").unwrap();
+        let address = server.listener.local_addr().unwrap();
+        let spectator = thread::spawn(move || {
+            let mut bytes = vec![];
+            TcpStream::connect(address).unwrap().read_to_end(&mut bytes).unwrap();
+            bytes
+        });
+        let game = Game::new();
+        let small = encode_frame(1, &[]);
+        let large = encode_frame(0, &[&game, &game]);
+        server.max_frame_size = large.len() - 5;
+        while server.spectators() == 0 {
+            server.broadcast(0, &[&game, &game]);
+        }
+        server.broadcast(1, &[]);
+        server.broadcast(2, &[&game, &game]);
+        drop(server);
+
+        // Only the frame within the limit arrived, and the connection ended cleanly after it
+        let bytes = spectator.join().unwrap();
+        let mut reader = &bytes[..];
+        read_header(&mut reader).unwrap();
+        assert_eq!(reader, &small[..]);
+    }
+}
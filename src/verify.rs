@@ -0,0 +1,182 @@
+// Checks that splitting a polyomino conserves blocks, momentum and angular momentum.
+//
+// The split fragments, together with the parts that were removed from the cleared line, should
+// carry exactly the momentum of the original object. The checks run on random splits of every
+// polyomino in the tests, and on every split in a live game with the `split-checks` feature.
+
+use na::{Point2, Vector2, Rotate};
+
+use clipping;
+use consts::*;
+use game::*;
+
+const RELATIVE_TOLERANCE: f32 = 1e-3;
+const ABSOLUTE_TOLERANCE: f32 = 1e-5;
+
+// Linear momentum, and angular momentum around the origin of the world
+#[derive(Copy, Clone, Debug)]
+pub struct Momentum {
+    pub mass: f32,
+    pub linear: Vector2<f32>,
+    pub angular: f32,
+}
+
+impl Momentum {
+    fn zero() -> Self {
+        Momentum { mass: 0.0, linear: Vector2::new(0.0, 0.0), angular: 0.0 }
+    }
+
+    fn add(self, other: Momentum) -> Self {
+        Momentum {
+            mass: self.mass + other.mass,
+            linear: self.linear + other.linear,
+            angular: self.angular + other.angular,
+        }
+    }
+}
+
+fn cross(a: Vector2<f32>, b: Vector2<f32>) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+// The momentum of a polygon (in world coordinates) that moves along with the given object
+fn polygon_momentum(tetr: &Tetromino, poly: &[Point2<f32>]) -> Momentum {
+    let rb = tetr.rbh.borrow();
+    let (mass, center, inertia) = clipping::mass_properties(poly, tetr.density);
+    let ang_vel = rb.ang_vel().x;
+    let offset = center - *rb.center_of_mass();
+    let velocity = rb.lin_vel() + Vector2::new(-ang_vel * offset.y, ang_vel * offset.x);
+    Momentum {
+        mass: mass,
+        linear: velocity * mass,
+        angular: cross(center.to_vector(), velocity * mass) + inertia * ang_vel,
+    }
+}
+
+fn world_outlines(tetr: &Tetromino) -> Vec<Vec<Point2<f32>>> {
+    let iso = *tetr.rbh.borrow().position();
+    tetr.blocks().iter()
+        .map(|block| block_outline(block).into_iter().map(|pt| iso * pt).collect())
+        .collect()
+}
+
+pub fn momentum(tetr: &Tetromino) -> Momentum {
+    world_outlines(tetr).iter()
+        .fold(Momentum::zero(), |sum, outline| sum.add(polygon_momentum(tetr, outline)))
+}
+
+// The momentum of everything that disappears when the given line is cleared from an object
+fn removed_momentum(tetr: &Tetromino, y_pos: f32, threshold: f32, mode: SplitMode) -> Momentum {
+    let iso = *tetr.rbh.borrow().position();
+    let removed: Vec<Vec<Point2<f32>>> = match mode {
+        SplitMode::Remove => {
+            tetr.blocks().iter()
+                .filter(|&&(inner_iso, _)| {
+                    let y = (iso.translation + iso.rotation.rotate(&inner_iso.translation)).y;
+                    (y - y_pos).abs() <= threshold * 1.01
+                })
+                .map(|block| block_outline(block).into_iter().map(|pt| iso * pt).collect())
+                .collect()
+        },
+        SplitMode::Cut => {
+            let (y_min, y_max) = (y_pos - threshold, y_pos + threshold);
+            world_outlines(tetr).into_iter()
+                .flat_map(|outline| {
+                    let (below, above) = clipping::cut_band(&outline, y_min, y_max);
+                    let inside = clipping::clip_half_plane(&outline, Vector2::new(0.0, -1.0), -y_min);
+                    let inside = clipping::clip_half_plane(&inside, Vector2::new(0.0, 1.0), y_max);
+                    // Untouched blocks are kept whole; slivers that are too small are dropped
+                    let touched = outline.iter().any(|pt| pt.y > y_min) && outline.iter().any(|pt| pt.y < y_max);
+                    let mut removed = vec![];
+                    if touched {
+                        removed.push(inside);
+                        removed.extend(vec![below, above].into_iter()
+                                       .filter(|part| part.len() >= 3 && clipping::area(part).abs() < MIN_FRAGMENT_AREA));
+                    }
+                    removed.into_iter()
+                })
+                .filter(|part| part.len() >= 3)
+                .collect()
+        },
+    };
+    removed.iter()
+        .fold(Momentum::zero(), |sum, poly| sum.add(polygon_momentum(tetr, poly)))
+}
+
+fn close(a: f32, b: f32, scale: f32) -> bool {
+    (a - b).abs() <= RELATIVE_TOLERANCE * scale + ABSOLUTE_TOLERANCE
+}
+
+// Check the result of splitting `parent` along the given line into `fragments`.
+pub fn check_split(parent: &Tetromino, fragments: &[Tetromino], y_pos: f32, threshold: f32, mode: SplitMode) -> Result<(), String> {
+    // Blocks are never duplicated, and only blocks from the parent end up in the fragments. A cut
+    // block can leave a fragment on either side of the line, which both keep its cell.
+    let mut cells: Vec<[i32; 2]> = fragments.iter().flat_map(|frag| frag.cells.iter().cloned()).collect();
+    for frag in fragments.iter() {
+        if frag.cells.len() != frag.blocks().len() {
+            return Err(format!("fragment has {} blocks but {} cells", frag.blocks().len(), frag.cells.len()));
+        }
+    }
+    cells.sort();
+    let max_copies = match mode {
+        SplitMode::Remove => 1,
+        SplitMode::Cut => 2,
+    };
+    if cells.windows(max_copies + 1).any(|run| run.iter().all(|&cell| cell == run[0])) {
+        return Err(format!("duplicated blocks in fragments: {:?}", cells));
+    }
+    if let Some(cell) = cells.iter().find(|cell| !parent.cells.contains(cell)) {
+        return Err(format!("fragment contains block {:?} that's not in the parent", cell));
+    }
+
+    let before = momentum(parent);
+    let after = fragments.iter()
+        .fold(removed_momentum(parent, y_pos, threshold, mode), |sum, frag| sum.add(momentum(frag)));
+
+    // Nothing is lost: the mass of the fragments and the removed parts adds up to the original
+    if !close(before.mass, after.mass, before.mass) {
+        return Err(format!("mass not conserved: {} before, {} after", before.mass, after.mass));
+    }
+    let speed_scale = before.mass * (before.linear.x.abs() + before.linear.y.abs() + 1.0);
+    if !close(before.linear.x, after.linear.x, speed_scale) || !close(before.linear.y, after.linear.y, speed_scale) {
+        return Err(format!("linear momentum not conserved: {:?} before, {:?} after", before.linear, after.linear));
+    }
+    let angular_scale = before.angular.abs() + speed_scale * (RIGHT - LEFT);
+    if !close(before.angular, after.angular, angular_scale) {
+        return Err(format!("angular momentum not conserved: {} before, {} after", before.angular, after.angular));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use consts::*;
+    use game::SplitMode;
+    use game::tests::{random_split, test_rng};
+    use super::check_split;
+
+    const SAMPLES_PER_SHAPE: usize = 200;
+
+    fn check_random_splits(mode: SplitMode) {
+        let mut rng = test_rng();
+        for (index, polyomino) in POLYOMINOS.iter().enumerate() {
+            for _ in 0..SAMPLES_PER_SHAPE {
+                if let Some((parent, fragments, y_pos, threshold)) = random_split(&mut rng, &polyomino[..], mode) {
+                    if let Err(message) = check_split(&parent, &fragments, y_pos, threshold, mode) {
+                        panic!("polyomino {} split along {} (half height {}): {}", index, y_pos, threshold, message);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn removing_blocks_conserves_momentum() {
+        check_random_splits(SplitMode::Remove);
+    }
+
+    #[test]
+    fn cutting_blocks_conserves_momentum() {
+        check_random_splits(SplitMode::Cut);
+    }
+}
@@ -0,0 +1,171 @@
+use rand::{self, Rng};
+
+use consts::*;
+use events::GameEvent;
+use game::Game;
+use modes::Mode;
+
+// How a round ended
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RoundEnd {
+    Winner(usize),
+    // Both wells topped out in the same update
+    Draw,
+}
+
+// Two games side by side. Clearing several lines at once sends garbage rows to the opponent, and
+// the first player whose well tops out loses.
+pub struct Versus {
+    games: [Game; 2],
+    // Garbage rows waiting to be pushed into each well
+    pending: [usize; 2],
+    sent: [usize; 2],
+    end: Option<RoundEnd>,
+    wins: [usize; 2],
+    seed: u32,
+}
+
+impl Versus {
+    pub fn new() -> Self {
+        let mut versus = Versus {
+            games: [Game::new(), Game::new()],
+            pending: [0, 0],
+            sent: [0, 0],
+            end: None,
+            wins: [0, 0],
+            seed: 0,
+        };
+        // Versus games can't be rewound, so they don't need snapshots
+        for game in versus.games.iter_mut() {
+            game.set_rewind_history(0);
+        }
+        versus.reset();
+        versus
+    }
+
+    pub fn game(&self, player: usize) -> &Game { &self.games[player] }
+    pub fn game_mut(&mut self, player: usize) -> &mut Game { &mut self.games[player] }
+    pub fn end(&self) -> Option<RoundEnd> { self.end }
+    pub fn over(&self) -> bool { self.end.is_some() }
+    pub fn wins(&self) -> [usize; 2] { self.wins }
+    pub fn seed(&self) -> u32 { self.seed }
+
+    // Garbage rows sent by a player so far
+    pub fn sent(&self, player: usize) -> usize { self.sent[player] }
+
+    // Start a new round with a random seed
+    pub fn reset(&mut self) {
+        let seed = rand::thread_rng().gen();
+        self.reseed(seed);
+    }
+
+    // Start a new round with the given seed. Both players get the same pieces.
+    pub fn reseed(&mut self, seed: u32) {
+        self.seed = seed;
+        for game in self.games.iter_mut() {
+            game.set_mode(Mode::Versus);
+            game.reseed(seed);
+        }
+        self.pending = [0, 0];
+        self.sent = [0, 0];
+        self.end = None;
+    }
+
+    // Update both games. Returns the events of both games, together with the player they're from.
+    pub fn update(&mut self) -> Vec<(usize, GameEvent)> {
+        let mut events = vec![];
+        // Once the round is over, both wells are frozen
+        if self.over() {
+            return events;
+        }
+        for player in 0..2 {
+            let opponent = 1 - player;
+            // Garbage comes in between line clears
+            if self.pending[player] > 0 && self.games[player].clear_progress().is_none() {
+                for _ in 0..self.pending[player] {
+                    self.games[player].add_garbage_row();
+                }
+                self.pending[player] = 0;
+            }
+
+            self.games[player].update();
+            for event in self.games[player].drain_events() {
+                if let GameEvent::LinesCleared { ref heights, .. } = event {
+                    let rows = VERSUS_GARBAGE[::std::cmp::min(heights.len(), VERSUS_GARBAGE.len() - 1)];
+                    self.pending[opponent] += rows;
+                    self.sent[player] += rows;
+                }
+                events.push((player, event));
+            }
+        }
+
+        let lost: Vec<bool> = self.games.iter().map(|game| game.outcome().is_some()).collect();
+        self.end = match (lost[0], lost[1]) {
+            (true, false) => Some(RoundEnd::Winner(1)),
+            (false, true) => Some(RoundEnd::Winner(0)),
+            (true, true) => Some(RoundEnd::Draw),
+            (false, false) => None,
+        };
+        if let Some(RoundEnd::Winner(winner)) = self.end {
+            self.wins[winner] += 1;
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use consts::VERSUS_GARBAGE;
+    use events::GameEvent;
+    use lines::LineDetector;
+    use super::{RoundEnd, Versus};
+
+    #[test]
+    fn clearing_lines_sends_garbage() {
+        let mut versus = Versus::new();
+        versus.reseed(11);
+        // Garbage rows have a gap, so they only count as lines with a lenient detector
+        versus.game_mut(0).set_line_detector(LineDetector::AreaCoverage(0.5));
+        for _ in 0..3 {
+            versus.game_mut(0).add_garbage_row();
+        }
+        let mut cleared = None;
+        for _ in 0..10 {
+            for (player, event) in versus.update() {
+                if let GameEvent::LinesCleared { ref heights, .. } = event {
+                    assert_eq!(player, 0);
+                    cleared = Some(heights.len());
+                }
+            }
+            if cleared.is_some() { break; }
+        }
+        let lines = cleared.expect("no lines were cleared");
+        assert!(lines >= 2);
+        let rows = VERSUS_GARBAGE[::std::cmp::min(lines, VERSUS_GARBAGE.len() - 1)];
+        assert_eq!((versus.sent(0), versus.sent(1)), (rows, 0));
+
+        // The opponent gets the rows on its next update
+        let received = versus.update().into_iter()
+            .filter(|&(player, ref event)| player == 1 && match *event { GameEvent::GarbageAdded { .. } => true, _ => false })
+            .count();
+        assert_eq!(received, rows);
+    }
+
+    #[test]
+    fn topping_out_together_is_a_draw() {
+        let mut versus = Versus::new();
+        versus.reseed(12);
+        // Both wells are filled up to the same height, so they top out in the same update
+        for player in 0..2 {
+            for _ in 0..14 {
+                versus.game_mut(player).add_garbage_row();
+            }
+        }
+        for _ in 0..1000 {
+            versus.update();
+            if versus.over() { break; }
+        }
+        assert_eq!(versus.end(), Some(RoundEnd::Draw));
+        assert_eq!(versus.wins(), [0, 0]);
+    }
+}